@@ -1,30 +1,88 @@
 // SPDX-License-Identifier: GPL-2.0-only
 
-#[allow(dead_code)]
-/// modalias is not used completely here, so some functions are not used
-#[path = "src/modalias.rs"]
-mod modalias;
-
 use libbpf_cargo::SkeletonBuilder;
 use std::env;
+use std::fmt::Write;
 use std::path::{Path, PathBuf};
 
 const BPF_SOURCE_DIR: &str = "src/bpf/"; // relative to our git repo root
-const ATTACH_PROG: &str = "attach.bpf.c";
+/// The BPF programs bundled in the udev-hid-bpf binary as skeletons
+const WRAPPER_PROGS: &[&str] = &["attach.bpf.c", "monitor.bpf.c", "request.bpf.c"];
 const WRAPPER: &str = "./src/hid_bpf_wrapper.h";
+/// The HID Usage Tables the usage names in rdesc.rs are generated from
+const USAGE_TABLES: &str = "data/HidUsageTables.json";
 
 fn build_bpf_wrappers(src_dir: &Path, dst_dir: &Path) {
-    let attach_prog = PathBuf::from(&src_dir).join(ATTACH_PROG);
-    if !attach_prog.as_path().is_file() {
-        panic!("Unable to find {}", attach_prog.display())
+    for prog in WRAPPER_PROGS {
+        let prog_path = PathBuf::from(&src_dir).join(prog);
+        if !prog_path.as_path().is_file() {
+            panic!("Unable to find {}", prog_path.display())
+        }
+        println!("cargo:rerun-if-changed={}", prog_path.display());
+
+        let skel_file = dst_dir.join(prog.replace(".bpf.c", ".skel.rs"));
+        SkeletonBuilder::new()
+            .source(prog_path)
+            .build_and_generate(&skel_file)
+            .unwrap();
     }
-    println!("cargo:rerun-if-changed={}", attach_prog.display());
+}
 
-    let skel_file = dst_dir.join(ATTACH_PROG.replace(".bpf.c", ".skel.rs"));
-    SkeletonBuilder::new()
-        .source(attach_prog)
-        .build_and_generate(&skel_file)
-        .unwrap();
+/// Generate the USAGE_NAMES and GENERATED_USAGE_NAMES tables included by rdesc.rs
+fn build_usage_names(source_root: &Path, dst_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let path = source_root.join(USAGE_TABLES);
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let tables: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+    let pages = tables["UsagePages"]
+        .as_array()
+        .ok_or("UsagePages missing")?;
+
+    let mut names: Vec<(u64, Vec<(u64, &str)>)> = Vec::new();
+    let mut generated: Vec<(u64, &str)> = Vec::new();
+    for page in pages {
+        let page_id = page["Id"].as_u64().ok_or("Usage page without Id")?;
+        if let Some(prefix) = page["UsageIdGenerator"]["NamePrefix"].as_str() {
+            generated.push((page_id, prefix));
+        }
+        let mut usages: Vec<(u64, &str)> = page["UsageIds"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|usage| Some((usage["Id"].as_u64()?, usage["Name"].as_str()?)))
+            .collect();
+        usages.sort();
+        names.push((page_id, usages));
+    }
+    names.sort();
+    generated.sort();
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "/// (usage page, [(usage id, name)]) sorted by page and usage id"
+    )?;
+    writeln!(out, "static USAGE_NAMES: &[(u16, &[(u16, &str)])] = &[")?;
+    for (page, usages) in &names {
+        writeln!(out, "    ({page:#06x}, &[")?;
+        for (id, name) in usages {
+            writeln!(out, "        ({id:#06x}, {name:?}),")?;
+        }
+        writeln!(out, "    ]),")?;
+    }
+    writeln!(out, "];")?;
+    writeln!(
+        out,
+        "/// (usage page, name prefix) of the pages whose names are numbered"
+    )?;
+    writeln!(out, "static GENERATED_USAGE_NAMES: &[(u16, &str)] = &[")?;
+    for (page, prefix) in &generated {
+        writeln!(out, "    ({page:#06x}, {prefix:?}),")?;
+    }
+    writeln!(out, "];")?;
+
+    std::fs::write(dst_dir.join("usages.rs"), out)?;
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -41,7 +99,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("cargo:rustc-env=MESON_BINDIR={bindir}");
 
     let source_root = env::var("BPF_SOURCE_ROOT").unwrap_or(String::from("."));
-    let bpf_src_dir = PathBuf::from(source_root).join(BPF_SOURCE_DIR);
+    let bpf_src_dir = PathBuf::from(&source_root).join(BPF_SOURCE_DIR);
 
     let bpf_lookup_dirs =
         env::var("BPF_LOOKUP_DIRS").unwrap_or(String::from("/usr/local/lib/firmware/hid/bpf"));
@@ -51,6 +109,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let out_dir = PathBuf::from(out_dir);
 
     build_bpf_wrappers(&bpf_src_dir, &out_dir);
+    build_usage_names(Path::new(&source_root), &out_dir)?;
 
     Ok(())
 }
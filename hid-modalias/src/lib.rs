@@ -0,0 +1,547 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! HID modaliases as used by the kernel and udev, e.g.
+//! `hid:b0003g0001v0000256Cp0000006D` for a USB device in the generic group
+//! with vendor ID 0x256C and product ID 0x006D.
+//!
+//! A modalias is also a match for other modaliases: `Bus::Any`, `Group::Any`
+//! and a zero vendor or product ID match any value. As in a hwdb match, these
+//! wildcards are written as `*`, e.g. `hid:b0003g*v0000256Cp*`.
+
+/// An error parsing a modalias or one of its values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The string is not a modalias
+    Invalid(String),
+    /// The bus is not a 16-bit value
+    InvalidBus(usize),
+    /// The group is not a 16-bit value
+    InvalidGroup(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Invalid(modalias) => write!(f, "Invalid modalias '{modalias}'"),
+            Error::InvalidBus(bus) => write!(f, "Invalid bus {bus:#06X}"),
+            Error::InvalidGroup(group) => write!(f, "Invalid group {group:#06X}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+pub enum Bus {
+    Any,
+    PCI,
+    ISAPnP,
+    USB,
+    HIL,
+    Bluetooth,
+    Virtual,
+    ISA,
+    I8042,
+    XtKbd,
+    Rs232,
+    GamePort,
+    ParPort,
+    Amiga,
+    ADB,
+    I2C,
+    Host,
+    GSC,
+    Atari,
+    SPI,
+    RMI,
+    CEC,
+    IntelIshtp,
+    AmdSfh,
+    /// A bus not listed here, e.g. one added by a newer kernel.
+    ///
+    /// This must never hold the value of a named variant: `Bus::Other(0x03)`
+    /// is not equal to `Bus::USB` and does not match it. Use Bus::from_u16()
+    /// or Bus::try_from() which return the named variant for known values.
+    Other(u16),
+}
+
+impl TryFrom<usize> for Bus {
+    type Error = Error;
+
+    fn try_from(sz: usize) -> Result<Self, Self::Error> {
+        u16::try_from(sz)
+            .map(Bus::from_u16)
+            .map_err(|_| Error::InvalidBus(sz))
+    }
+}
+
+impl From<&Bus> for usize {
+    fn from(bus: &Bus) -> Self {
+        match bus {
+            Bus::Any => 0x00,
+            Bus::PCI => 0x01,
+            Bus::ISAPnP => 0x02,
+            Bus::USB => 0x03,
+            Bus::HIL => 0x04,
+            Bus::Bluetooth => 0x05,
+            Bus::Virtual => 0x06,
+            Bus::ISA => 0x10,
+            Bus::I8042 => 0x11,
+            Bus::XtKbd => 0x12,
+            Bus::Rs232 => 0x13,
+            Bus::GamePort => 0x14,
+            Bus::ParPort => 0x15,
+            Bus::Amiga => 0x16,
+            Bus::ADB => 0x17,
+            Bus::I2C => 0x18,
+            Bus::Host => 0x19,
+            Bus::GSC => 0x1A,
+            Bus::Atari => 0x1B,
+            Bus::SPI => 0x1C,
+            Bus::RMI => 0x1D,
+            Bus::CEC => 0x1E,
+            Bus::IntelIshtp => 0x1F,
+            Bus::AmdSfh => 0x20,
+            Bus::Other(bus) => *bus as usize,
+        }
+    }
+}
+
+impl Bus {
+    /// The bus with the given value, the named variant for known
+    /// values and Bus::Other only for any other value
+    pub fn from_u16(bus: u16) -> Self {
+        match bus {
+            0x00 => Bus::Any,
+            0x01 => Bus::PCI,
+            0x02 => Bus::ISAPnP,
+            0x03 => Bus::USB,
+            0x04 => Bus::HIL,
+            0x05 => Bus::Bluetooth,
+            0x06 => Bus::Virtual,
+            0x10 => Bus::ISA,
+            0x11 => Bus::I8042,
+            0x12 => Bus::XtKbd,
+            0x13 => Bus::Rs232,
+            0x14 => Bus::GamePort,
+            0x15 => Bus::ParPort,
+            0x16 => Bus::Amiga,
+            0x17 => Bus::ADB,
+            0x18 => Bus::I2C,
+            0x19 => Bus::Host,
+            0x1A => Bus::GSC,
+            0x1B => Bus::Atari,
+            0x1C => Bus::SPI,
+            0x1D => Bus::RMI,
+            0x1E => Bus::CEC,
+            0x1F => Bus::IntelIshtp,
+            0x20 => Bus::AmdSfh,
+            _ => Bus::Other(bus),
+        }
+    }
+
+    /// The name of the bus as used in HID_DEVICE(), e.g. BUS_USB,
+    /// or the value in hex for Bus::Other
+    pub fn name(&self) -> String {
+        let name = match self {
+            Bus::Any => "BUS_ANY",
+            Bus::PCI => "BUS_PCI",
+            Bus::ISAPnP => "BUS_ISAPNP",
+            Bus::USB => "BUS_USB",
+            Bus::HIL => "BUS_HIL",
+            Bus::Bluetooth => "BUS_BLUETOOTH",
+            Bus::Virtual => "BUS_VIRTUAL",
+            Bus::ISA => "BUS_ISA",
+            Bus::I8042 => "BUS_I8042",
+            Bus::XtKbd => "BUS_XTKBD",
+            Bus::Rs232 => "BUS_RS232",
+            Bus::GamePort => "BUS_GAMEPORT",
+            Bus::ParPort => "BUS_PARPORT",
+            Bus::Amiga => "BUS_AMIGA",
+            Bus::ADB => "BUS_ADB",
+            Bus::I2C => "BUS_I2C",
+            Bus::Host => "BUS_HOST",
+            Bus::GSC => "BUS_GSC",
+            Bus::Atari => "BUS_ATARI",
+            Bus::SPI => "BUS_SPI",
+            Bus::RMI => "BUS_RMI",
+            Bus::CEC => "BUS_CEC",
+            Bus::IntelIshtp => "BUS_INTEL_ISHTP",
+            Bus::AmdSfh => "BUS_AMD_SFH",
+            Bus::Other(bus) => return format!("{bus:#06X}"),
+        };
+        String::from(name)
+    }
+}
+
+/// The bus as in a hwdb match, e.g. 0003 or * for Bus::Any
+impl std::fmt::Display for Bus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bus::Any => write!(f, "*"),
+            _ => write!(f, "{:04X}", self),
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+pub enum Group {
+    Any,
+    Generic,
+    Multitouch,
+    SensorHub,
+    MultitouchWin8,
+    RMI,
+    Wacom,
+    LogitechDJ,
+    Steam,
+    Logitech27mhz,
+    Vivaldi,
+    /// A group not listed here, e.g. a vendor group added by a newer kernel.
+    ///
+    /// This must never hold the value of a named variant: `Group::Other(0x01)`
+    /// is not equal to `Group::Generic` and does not match it. Use
+    /// Group::from_u16() or Group::try_from() which return the named variant
+    /// for known values.
+    Other(u16),
+}
+
+impl TryFrom<usize> for Group {
+    type Error = Error;
+
+    fn try_from(sz: usize) -> Result<Self, Self::Error> {
+        u16::try_from(sz)
+            .map(Group::from_u16)
+            .map_err(|_| Error::InvalidGroup(sz))
+    }
+}
+
+impl From<&Group> for usize {
+    fn from(group: &Group) -> Self {
+        match group {
+            Group::Any => 0x00,
+            Group::Generic => 0x01,
+            Group::Multitouch => 0x02,
+            Group::SensorHub => 0x03,
+            Group::MultitouchWin8 => 0x04,
+            Group::RMI => 0x0100,
+            Group::Wacom => 0x0101,
+            Group::LogitechDJ => 0x0102,
+            Group::Steam => 0x0103,
+            Group::Logitech27mhz => 0x0104,
+            Group::Vivaldi => 0x0105,
+            Group::Other(group) => *group as usize,
+        }
+    }
+}
+
+impl Group {
+    /// The group with the given value, the named variant for known
+    /// values and Group::Other only for any other value
+    pub fn from_u16(group: u16) -> Self {
+        match group {
+            0x00 => Group::Any,
+            0x01 => Group::Generic,
+            0x02 => Group::Multitouch,
+            0x03 => Group::SensorHub,
+            0x04 => Group::MultitouchWin8,
+            0x0100 => Group::RMI,
+            0x0101 => Group::Wacom,
+            0x0102 => Group::LogitechDJ,
+            0x0103 => Group::Steam,
+            0x0104 => Group::Logitech27mhz,
+            0x0105 => Group::Vivaldi,
+            _ => Group::Other(group),
+        }
+    }
+
+    /// The name of the group as used in HID_DEVICE(), e.g. HID_GROUP_GENERIC,
+    /// or the value in hex for Group::Other
+    pub fn name(&self) -> String {
+        let name = match self {
+            Group::Any => "HID_GROUP_ANY",
+            Group::Generic => "HID_GROUP_GENERIC",
+            Group::Multitouch => "HID_GROUP_MULTITOUCH",
+            Group::SensorHub => "HID_GROUP_SENSOR_HUB",
+            Group::MultitouchWin8 => "HID_GROUP_MULTITOUCH_WIN_8",
+            Group::RMI => "HID_GROUP_RMI",
+            Group::Wacom => "HID_GROUP_WACOM",
+            Group::LogitechDJ => "HID_GROUP_LOGITECH_DJ_DEVICE",
+            Group::Steam => "HID_GROUP_STEAM",
+            Group::Logitech27mhz => "HID_GROUP_LOGITECH_27MHZ_DEVICE",
+            Group::Vivaldi => "HID_GROUP_VIVALDI",
+            Group::Other(group) => return format!("{group:#06X}"),
+        };
+        String::from(name)
+    }
+}
+
+/// The group as in a hwdb match, e.g. 0001 or * for Group::Any
+impl std::fmt::Display for Group {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Group::Any => write!(f, "*"),
+            _ => write!(f, "{:04X}", self),
+        }
+    }
+}
+
+impl std::fmt::UpperHex for Bus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let val: usize = self.into();
+        std::fmt::UpperHex::fmt(&val, f)
+    }
+}
+
+impl std::fmt::LowerHex for Bus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let val: usize = self.into();
+        std::fmt::LowerHex::fmt(&val, f)
+    }
+}
+
+impl std::fmt::UpperHex for Group {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let val: usize = self.into();
+        std::fmt::UpperHex::fmt(&val, f)
+    }
+}
+
+impl std::fmt::LowerHex for Group {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let val: usize = self.into();
+        std::fmt::LowerHex::fmt(&val, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct Modalias {
+    pub bus: Bus,
+    pub group: Group,
+    /// The vendor ID, 0 matches any vendor
+    pub vid: u32,
+    /// The product ID, 0 matches any product
+    pub pid: u32,
+}
+
+impl Default for Modalias {
+    /// A modalias that matches any device
+    fn default() -> Self {
+        Modalias {
+            bus: Bus::Any,
+            group: Group::Any,
+            vid: 0,
+            pid: 0,
+        }
+    }
+}
+
+impl Modalias {
+    /// Whether the device's modalias matches this modalias, where any field
+    /// that is Any or zero in this modalias matches any value
+    pub fn matches(&self, device: &Modalias) -> bool {
+        (self.bus == Bus::Any || self.bus == device.bus)
+            && (self.group == Group::Any || self.group == device.group)
+            && (self.vid == 0 || self.vid == device.vid)
+            && (self.pid == 0 || self.pid == device.pid)
+    }
+
+    /// The modalias as the kernel writes it, without wildcards,
+    /// e.g. hid:b0003g0000v0000256Cp00000000
+    pub fn to_kernel_string(&self) -> String {
+        format!(
+            "hid:b{:04X}g{:04X}v{:08X}p{:08X}",
+            self.bus, self.group, self.vid, self.pid
+        )
+    }
+}
+
+/// Parse a modalias in the kernel or the hwdb form, the hid: prefix is optional.
+/// Each field is either a hex number with the fixed number of digits
+/// (4 for bus and group, 8 for vid and pid) or a * wildcard.
+impl std::str::FromStr for Modalias {
+    type Err = Error;
+
+    fn from_str(modalias: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Invalid(String::from(modalias));
+
+        let mut rest = modalias.strip_prefix("hid:").unwrap_or(modalias);
+        let mut field = |prefix: char, digits: usize| -> Result<usize, Error> {
+            rest = rest.strip_prefix(prefix).ok_or_else(invalid)?;
+            if let Some(r) = rest.strip_prefix('*') {
+                rest = r;
+                return Ok(0);
+            }
+            let value = rest.get(..digits).ok_or_else(invalid)?;
+            if !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            rest = &rest[digits..];
+            usize::from_str_radix(value, 16).map_err(|_| invalid())
+        };
+
+        let bus = Bus::try_from(field('b', 4)?)?;
+        let group = Group::try_from(field('g', 4)?)?;
+        let vid = field('v', 8)? as u32;
+        let pid = field('p', 8)? as u32;
+        if !rest.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            bus,
+            group,
+            vid,
+            pid,
+        })
+    }
+}
+
+/// The modalias as hwdb match, e.g. hid:b0003g*v0000256Cp0000006D
+impl std::fmt::Display for Modalias {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let id = |id: u32| match id {
+            0 => String::from("*"),
+            _ => format!("{id:08X}"),
+        };
+        write!(
+            f,
+            "hid:b{}g{}v{}p{}",
+            self.bus,
+            self.group,
+            id(self.vid),
+            id(self.pid)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_modalias() {
+        let modalias = "b0003g0001v000004D9p0000A09F";
+        let m = Modalias::from_str(modalias);
+        assert!(m.is_ok());
+        let m = m.unwrap();
+        assert!(m.bus == Bus::USB);
+        assert!(m.group == Group::Generic);
+        assert!(m.vid == 0x04d9);
+        assert!(m.pid == 0xa09f);
+
+        // parsing doesn't care about uppercase hex
+        let m = Modalias::from_str(modalias.to_lowercase().as_str());
+        assert!(m.is_ok());
+        let m = m.unwrap();
+        assert!(m.bus == Bus::USB);
+        assert!(m.group == Group::Generic);
+        assert!(m.vid == 0x04d9);
+        assert!(m.pid == 0xa09f);
+
+        // 4-digit vid
+        let modalias = "b0003g0001v04D9p0000A09F";
+        let m = Modalias::from_str(modalias.to_lowercase().as_str());
+        assert!(m.is_err());
+
+        // 4-digit pid
+        let modalias = "b0003g0001v000004D9pA09F";
+        let m = Modalias::from_str(modalias.to_lowercase().as_str());
+        assert!(m.is_err());
+
+        // invalid char
+        let modalias = "b0003g0001v0000g4D9pA09F";
+        let m = Modalias::from_str(modalias.to_lowercase().as_str());
+        assert!(m.is_err());
+    }
+
+    #[test]
+    fn test_unknown_values() {
+        let m = Modalias::from_str("hid:b0042g0106v000004D9p0000A09F").unwrap();
+        assert_eq!(m.bus, Bus::Other(0x42));
+        assert_eq!(m.group, Group::Other(0x106));
+        assert_eq!(m.to_string(), "hid:b0042g0106v000004D9p0000A09F");
+        assert_eq!(m.bus.name(), "0x0042");
+        assert_eq!(m.group.name(), "0x0106");
+
+        assert_eq!(Bus::try_from(0x03), Ok(Bus::USB));
+        assert_eq!(usize::from(&Bus::Other(0x42)), 0x42);
+        assert_eq!(Bus::try_from(0x10000), Err(Error::InvalidBus(0x10000)));
+        assert_eq!(Group::try_from(0x0101), Ok(Group::Wacom));
+        assert_eq!(usize::from(&Group::Other(0x106)), 0x106);
+        assert_eq!(Group::try_from(0x10000), Err(Error::InvalidGroup(0x10000)));
+        assert_eq!(Bus::USB.name(), "BUS_USB");
+        assert_eq!(Group::Generic.name(), "HID_GROUP_GENERIC");
+
+        assert!(Modalias::from_str("hid:b0003g0001v000004D9p0000A09F0").is_err());
+        assert!(Modalias::from_str("hid:b0003g0001v+00004D9p0000A09F").is_err());
+        assert!(Modalias::from_str("hid:empty").is_err());
+        assert!(Modalias::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_from_u16() {
+        assert_eq!(Bus::from_u16(0x03), Bus::USB);
+        assert_eq!(Bus::from_u16(0x00), Bus::Any);
+        assert_eq!(Bus::from_u16(0x42), Bus::Other(0x42));
+        assert_eq!(Group::from_u16(0x0101), Group::Wacom);
+        assert_eq!(Group::from_u16(0x0106), Group::Other(0x0106));
+
+        // Each value has exactly one variant
+        for value in 0..=u16::MAX {
+            let bus = Bus::from_u16(value);
+            assert_eq!(usize::from(&bus), value as usize);
+            let group = Group::from_u16(value);
+            assert_eq!(usize::from(&group), value as usize);
+        }
+
+        let device = Modalias {
+            bus: Bus::from_u16(0x03),
+            group: Group::from_u16(0x01),
+            vid: 0x256c,
+            pid: 0x006d,
+        };
+        let pattern = Modalias::from_str("hid:b0003g0001v*p*").unwrap();
+        assert!(pattern.matches(&device));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for modalias in [
+            "hid:b0003g0001v0000256Cp0000006D",
+            "hid:b0003g*v0000256Cp0000006D",
+            "hid:b0005g0102v*p*",
+            "hid:b*g*v*p*",
+        ] {
+            let m = Modalias::from_str(modalias).unwrap();
+            assert_eq!(m.to_string(), modalias);
+            assert_eq!(Modalias::from_str(&m.to_kernel_string()).unwrap(), m);
+        }
+
+        let m = Modalias::from_str("b0003g*v0000256Cp*").unwrap();
+        assert_eq!(m.to_kernel_string(), "hid:b0003g0000v0000256Cp00000000");
+        assert_eq!(m, Modalias::from_str(&m.to_kernel_string()).unwrap());
+    }
+
+    #[test]
+    fn test_matches() {
+        let device = Modalias::from_str("hid:b0003g0001v0000256Cp0000006D").unwrap();
+
+        for (pattern, matches) in [
+            ("hid:b0003g0001v0000256Cp0000006D", true),
+            ("hid:b0003g*v0000256Cp0000006D", true),
+            ("hid:b0003g*v0000256Cp*", true),
+            ("hid:b*g*v*p*", true),
+            ("hid:b0005g*v0000256Cp0000006D", false),
+            ("hid:b0003g0002v0000256Cp0000006D", false),
+            ("hid:b0003g*v0000256Cp0000006E", false),
+        ] {
+            let pattern = Modalias::from_str(pattern).unwrap();
+            assert_eq!(pattern.matches(&device), matches, "{pattern}");
+        }
+        assert!(Modalias::default().matches(&device));
+    }
+}
@@ -3,16 +3,29 @@
 include!(concat!(env!("OUT_DIR"), "/attach.skel.rs"));
 
 use crate::hidudev;
-use anyhow::{bail, Context, Result};
+use crate::modalias::ObjectInfo;
+use crate::objfile;
+use crate::report::Outcome;
+use crate::signature;
+use anyhow::{Context, Result};
 use libbpf_rs::skel::{OpenSkel, SkelBuilder};
 use libbpf_rs::{AsRawLibbpf, Btf, Object, OpenObject, Program};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Display;
 use std::fs;
+use std::io::Write;
 use std::os::fd::{AsFd, AsRawFd};
-use std::os::raw::{c_int, c_uchar, c_uint};
-use std::path::Path;
-use std::sync::OnceLock;
+use std::os::raw::{c_char, c_int, c_uchar, c_uint};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Size of the verifier log buffer of each program, libbpf's own maximum
+const VERIFIER_LOG_SIZE: usize = 16 * 1024 * 1024;
+
+/// The file to append the full verifier log to, see set_verifier_log_file()
+static VERIFIER_LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -34,9 +47,18 @@ pub struct AttachProgArgs {
 
 #[derive(Debug)]
 pub enum BpfError {
-    LibBPFError { error: libbpf_rs::Error },
-    OsError { errno: u32 },
+    LibBPFError {
+        error: libbpf_rs::Error,
+    },
+    OsError {
+        errno: u32,
+    },
     Unsupported,
+    VerifierError {
+        error: libbpf_rs::Error,
+        program: String,
+        log: String,
+    },
 }
 
 impl std::error::Error for BpfError {}
@@ -49,6 +71,16 @@ impl Display for BpfError {
                 write!(f, "{}", libbpf_rs::Error::from_raw_os_error(*errno as i32))
             }
             BpfError::Unsupported => write!(f, "unsupported on this kernel"),
+            BpfError::VerifierError {
+                error,
+                program,
+                log,
+            } => {
+                write!(f, "{error}, program '{program}' rejected by the verifier:")?;
+                verifier_log_tail(log)
+                    .iter()
+                    .try_for_each(|l| write!(f, "\n    {l}"))
+            }
         }
     }
 }
@@ -59,10 +91,180 @@ impl From<libbpf_rs::Error> for BpfError {
     }
 }
 
+/// An error while loading a bpf.o file for a device
+/// together with the outcome it results in.
+#[derive(Debug)]
+pub struct LoadError {
+    pub outcome: Outcome,
+    pub error: anyhow::Error,
+}
+
+impl std::error::Error for LoadError {}
+
+impl LoadError {
+    /// The errno of the underlying error, if any
+    pub fn errno(&self) -> Option<i32> {
+        // libbpf_rs::Error doesn't expose its errno, only its
+        // io::Error's "Permission denied (os error 13)" message
+        let from_message = |e: &dyn std::error::Error| -> Option<i32> {
+            let message = e.to_string();
+            let (_, errno) = message.rsplit_once("(os error ")?;
+            errno.strip_suffix(')')?.parse().ok()
+        };
+
+        self.error
+            .chain()
+            .find_map(|e| match e.downcast_ref::<BpfError>() {
+                Some(BpfError::OsError { errno }) => Some(*errno as i32),
+                Some(BpfError::LibBPFError { error })
+                | Some(BpfError::VerifierError { error, .. }) => from_message(error),
+                Some(BpfError::Unsupported) => Some(libc::ENOTSUP),
+                None => e
+                    .downcast_ref::<libbpf_rs::Error>()
+                    .and_then(|e| from_message(e)),
+            })
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+trait WithOutcome<T> {
+    fn outcome(self, outcome: Outcome, context: String) -> Result<T, LoadError>;
+}
+
+impl<T, E: Into<anyhow::Error>> WithOutcome<T> for std::result::Result<T, E> {
+    fn outcome(self, outcome: Outcome, context: String) -> Result<T, LoadError> {
+        self.map_err(|e| LoadError {
+            outcome,
+            error: e.into().context(context),
+        })
+    }
+}
+
+impl BpfError {
+    /// The outcome of a failure to load an object
+    fn load_outcome(&self) -> Outcome {
+        match self {
+            BpfError::VerifierError { .. } => Outcome::VerifierRejected,
+            BpfError::Unsupported => Outcome::Unsupported,
+            BpfError::OsError { errno } if *errno == libc::ENOTSUP as u32 => Outcome::Unsupported,
+            _ => Outcome::LoadFailed,
+        }
+    }
+}
+
 pub struct HidBPF {}
 
+/// Save the full verifier log of every program rejected by the
+/// verifier to the given file. The file is truncated first.
+pub fn set_verifier_log_file(path: &Path) -> Result<()> {
+    fs::File::create(path).context(format!("Failed to create {path:?}"))?;
+    let _ = VERIFIER_LOG_FILE.set(path.into());
+    Ok(())
+}
+
+/// Extract the interesting bits of a verifier log: the source line
+/// (from the BTF line info) of the instruction the verifier failed on,
+/// that instruction and the verifier's error message.
+fn verifier_log_tail(log: &str) -> Vec<&str> {
+    let lines: Vec<&str> = log
+        .lines()
+        .map(|l| l.trim_end())
+        .filter(|l| !l.is_empty())
+        // statistics printed at the end of every log
+        .filter(|l| {
+            !l.starts_with("processed ")
+                && !l.starts_with("verification time")
+                && !l.starts_with("stack depth")
+        })
+        .collect();
+
+    let is_insn = |l: &&str| {
+        l.split_once(": (")
+            .is_some_and(|(idx, _)| !idx.is_empty() && idx.chars().all(|c| c.is_ascii_digit()))
+    };
+
+    match lines.iter().rposition(is_insn) {
+        Some(idx) => lines[..idx]
+            .iter()
+            .rfind(|l| l.starts_with("; "))
+            .into_iter()
+            .chain(&lines[idx..])
+            .copied()
+            .collect(),
+        None => lines[lines.len().saturating_sub(3)..].to_vec(),
+    }
+}
+
+/// The verifier log buffers of all programs in an object
+struct VerifierLogs {
+    buffers: Vec<(String, Vec<u8>)>,
+}
+
+impl VerifierLogs {
+    /// Give each program of the object its own log buffer. libbpf
+    /// only requests the verifier log (and fills our buffer)
+    /// if loading the program fails. The buffers are large, only
+    /// attach them to retry a load that failed, see load_object().
+    fn attach(open_object: &mut OpenObject) -> Self {
+        let buffers = open_object
+            .progs_iter_mut()
+            .map(|prog| {
+                let name = String::from(prog.name().unwrap_or_default());
+                let mut buffer = vec![0u8; VERIFIER_LOG_SIZE];
+                unsafe {
+                    libbpf_sys::bpf_program__set_log_buf(
+                        prog.as_libbpf_object().as_ptr(),
+                        buffer.as_mut_ptr() as *mut c_char,
+                        buffer.len() as libbpf_sys::size_t,
+                    );
+                }
+                (name, buffer)
+            })
+            .collect();
+
+        VerifierLogs { buffers }
+    }
+
+    /// Convert a load error into a BpfError::VerifierError if
+    /// one of the programs has a verifier log and save that log
+    /// if requested.
+    fn into_error(self, e: BpfError, object_name: &str) -> BpfError {
+        let BpfError::LibBPFError { error } = e else {
+            return e;
+        };
+
+        let Some((program, log)) = self.buffers.into_iter().find_map(|(name, buffer)| {
+            let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+            (len > 0).then(|| (name, String::from_utf8_lossy(&buffer[..len]).into_owned()))
+        }) else {
+            return BpfError::LibBPFError { error };
+        };
+
+        if let Some(path) = VERIFIER_LOG_FILE.get() {
+            if let Err(e) = fs::OpenOptions::new()
+                .append(true)
+                .open(path)
+                .and_then(|mut f| writeln!(f, "# {object_name}: program '{program}'\n{log}"))
+            {
+                log::warn!("Failed to write verifier log to {path:?}: {e}");
+            }
+        }
+
+        BpfError::VerifierError {
+            error,
+            program,
+            log,
+        }
+    }
+}
+
 pub trait HidBPFLoader {
-    fn load(&self, object: OpenObject, _device: &hidudev::HidUdev) -> Result<Object, BpfError> {
+    fn load(&self, object: OpenObject, _hid_id: u32) -> Result<Object, BpfError> {
         Ok(object.load()?)
     }
 
@@ -184,7 +386,10 @@ pub fn remove_bpf_objects(sysname: &str) -> std::io::Result<()> {
     Ok(())
 }
 
-fn run_syscall_prog_generic<T>(prog: &libbpf_rs::Program, data: T) -> Result<T, BpfError> {
+pub(crate) fn run_syscall_prog_generic<T>(
+    prog: &libbpf_rs::Program,
+    data: T,
+) -> Result<T, BpfError> {
     let fd = prog.as_fd().as_raw_fd();
     let data_ptr: *const libc::c_void = &data as *const _ as *const libc::c_void;
     let mut run_opts = libbpf_sys::bpf_test_run_opts {
@@ -345,7 +550,7 @@ impl<'a> HidBPFTrace<'a> {
 }
 
 impl<'a> HidBPFLoader for HidBPFTrace<'a> {
-    fn load(&self, object: OpenObject, _device: &hidudev::HidUdev) -> Result<Object, BpfError> {
+    fn load(&self, object: OpenObject, _hid_id: u32) -> Result<Object, BpfError> {
         match self.inner {
             None => Err(BpfError::OsError {
                 errno: libc::ENOTSUP as u32,
@@ -366,12 +571,8 @@ impl<'a> HidBPFLoader for HidBPFTrace<'a> {
 }
 
 impl HidBPFLoader for HidBPFStructOps {
-    fn load(
-        &self,
-        mut open_object: OpenObject,
-        device: &hidudev::HidUdev,
-    ) -> Result<Object, BpfError> {
-        let bytes_hid_id: [u8; 4] = device.id().to_le_bytes();
+    fn load(&self, mut open_object: OpenObject, hid_id: u32) -> Result<Object, BpfError> {
+        let bytes_hid_id: [u8; 4] = hid_id.to_le_bytes();
 
         open_object
             .maps_iter_mut()
@@ -421,24 +622,171 @@ impl HidBPFLoader for HidBPFStructOps {
     }
 }
 
+static HID_BPF_TRACE: OnceLock<HidBPFTrace> = OnceLock::new();
+static HID_BPF_STRUCT_OPS: OnceLock<HidBPFStructOps> = OnceLock::new();
+
+/// The kernel interface an object is built for
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// HID-BPF struct_ops, since kernel 6.11
+    StructOps,
+    /// Tracing programs attached with hid_bpf_attach_prog(), before kernel 6.11
+    Tracing,
+}
+
+impl Backend {
+    pub fn of(open_object: &OpenObject) -> Self {
+        let have_struct_ops: bool = open_object.progs_iter().any(|p| {
+            matches!(p.prog_type(), libbpf_rs::ProgramType::StructOps)
+                && p.section().starts_with("struct_ops/hid_")
+        });
+        match have_struct_ops {
+            true => Backend::StructOps,
+            false => Backend::Tracing,
+        }
+    }
+
+    /// The backend of the bpf.o file at path
+    pub fn of_objfile(path: &Path) -> Result<Self> {
+        ObjfileData::get(path)
+            .and_then(|objfile| objfile.backend.clone())
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Whether the running kernel supports this backend
+    pub fn supported(&self) -> bool {
+        static STRUCT_OPS: OnceLock<bool> = OnceLock::new();
+        match self {
+            Backend::StructOps => *STRUCT_OPS.get_or_init(kernel_has_hid_bpf_ops),
+            Backend::Tracing => HID_BPF_TRACE
+                .get_or_init(HidBPFTrace::default)
+                .inner
+                .is_some(),
+        }
+    }
+}
+
+/// Whether struct hid_bpf_ops is in the BTF of the kernel or, if HID
+/// is built as a module, the BTF of the hid module
+fn kernel_has_hid_bpf_ops() -> bool {
+    let name = std::ffi::CString::new("hid_bpf_ops").unwrap();
+    let module = std::ffi::CString::new("hid").unwrap();
+    unsafe {
+        let vmlinux = libbpf_sys::btf__load_vmlinux_btf();
+        if vmlinux.is_null() {
+            return false;
+        }
+        let mut found =
+            libbpf_sys::btf__find_by_name_kind(vmlinux, name.as_ptr(), libbpf_sys::BTF_KIND_STRUCT)
+                >= 0;
+        if !found {
+            let hid = libbpf_sys::btf__load_module_btf(module.as_ptr(), vmlinux);
+            if !hid.is_null() {
+                found = libbpf_sys::btf__find_by_name_kind(
+                    hid,
+                    name.as_ptr(),
+                    libbpf_sys::BTF_KIND_STRUCT,
+                ) >= 0;
+                libbpf_sys::btf__free(hid);
+            }
+        }
+        libbpf_sys::btf__free(vmlinux);
+        found
+    }
+}
+
 fn get_bpf_loader(open_object: &OpenObject) -> &'static dyn HidBPFLoader {
-    static HID_BPF_TRACE: OnceLock<HidBPFTrace> = OnceLock::new();
-    static HID_BPF_STRUCT_OPS: OnceLock<HidBPFStructOps> = OnceLock::new();
+    match Backend::of(open_object) {
+        Backend::Tracing => {
+            log::debug!("Using HID_BPF_TRACE");
+            HID_BPF_TRACE.get_or_init(HidBPFTrace::default)
+        }
+        Backend::StructOps => {
+            log::debug!("Using HID_BPF_STRUCT_OPS");
+            HID_BPF_STRUCT_OPS.get_or_init(HidBPFStructOps::default)
+        }
+    }
+}
 
-    let have_struct_ops: bool = open_object.progs_iter().any(|p| {
-        matches!(p.prog_type(), libbpf_rs::ProgramType::StructOps)
-            && p.section().starts_with("struct_ops/hid_")
-    });
+/// A bpf.o file and everything derived from its contents, read only once
+/// per invocation and shared by all devices and jobs, see ObjfileData::get()
+pub struct ObjfileData {
+    /// The decompressed contents
+    pub data: Vec<u8>,
+    /// The metadata, an error if it is invalid
+    pub info: Result<ObjectInfo, String>,
+    /// The backend the file is built for, an error if it cannot be opened
+    pub backend: Result<Backend, String>,
+    /// The result of the signature check, checked when the file is
+    /// first loaded, see HidBPF::read_objfile()
+    signature: OnceLock<Result<(), String>>,
+}
 
-    if !have_struct_ops {
-        log::debug!("Using HID_BPF_TRACE");
-        HID_BPF_TRACE.get_or_init(HidBPFTrace::default)
-    } else {
-        log::debug!("Using HID_BPF_STRUCT_OPS");
-        HID_BPF_STRUCT_OPS.get_or_init(HidBPFStructOps::default)
+/// A cache entry of ObjfileData::get(), set by the first caller for its path
+type ObjfileCell = Arc<OnceLock<Result<Arc<ObjfileData>, String>>>;
+
+impl ObjfileData {
+    /// The data of the bpf.o file at path, read, decompressed and parsed once.
+    /// The cache is only locked to look up the entry of the path, a file is
+    /// read by its first caller while other callers for the same file wait.
+    pub fn get(path: &Path) -> Result<Arc<ObjfileData>, String> {
+        static OBJFILES: OnceLock<Mutex<HashMap<PathBuf, ObjfileCell>>> = OnceLock::new();
+        let cell = Arc::clone(
+            OBJFILES
+                .get_or_init(Default::default)
+                .lock()
+                .unwrap()
+                .entry(PathBuf::from(path))
+                .or_default(),
+        );
+        let objfile = cell.get_or_init(|| {
+            objfile::read(path)
+                .map(|data| Arc::new(Self::parse(path, data)))
+                .map_err(|e| format!("{e:#}"))
+        });
+        objfile.clone()
+    }
+
+    fn parse(path: &Path, data: Vec<u8>) -> Self {
+        let info = ObjectInfo::from_data(path, &data).map_err(|e| format!("{e:#}"));
+        let backend = libbpf_rs::ObjectBuilder::default()
+            .name(objfile::filename(path))
+            .and_then(|builder| builder.open_memory(&data))
+            .map(|object| Backend::of(&object))
+            .map_err(|e| format!("Failed to open {path:?}: {e}"));
+        ObjfileData {
+            data,
+            info,
+            backend,
+            signature: OnceLock::new(),
+        }
     }
 }
 
+/// The variant of the bpf.o file to load, see objfile::variants(): the first
+/// variant built for a backend the running kernel supports or the file itself
+/// if there is no such variant
+fn select_variant(path: &Path) -> PathBuf {
+    let variants = objfile::variants(path);
+    if variants.len() == 1 {
+        return PathBuf::from(path);
+    }
+    variants
+        .into_iter()
+        .find(|variant| match Backend::of_objfile(variant) {
+            Ok(backend) => {
+                log::debug!("{variant:?} uses the {backend:?} backend");
+                backend.supported()
+            }
+            Err(e) => {
+                log::warn!("{e:#}");
+                false
+            }
+        })
+        .unwrap_or_else(|| PathBuf::from(path))
+}
+
 impl HidBPF {
     fn pin_maps(object: &mut Object, bpffs_path: &String) -> Result<()> {
         // compiler internal maps contain the name of the object and a dot
@@ -457,44 +805,179 @@ impl HidBPF {
         Ok(())
     }
 
+    /// The selected variant of the bpf.o file and its data, see
+    /// ObjfileData::get(). The signature of each variant is checked once.
+    fn read_objfile(path: &Path) -> Result<(PathBuf, Arc<ObjfileData>), LoadError> {
+        let variant = select_variant(path);
+        let object_name = &objfile::object_name(&variant);
+        let objfile = ObjfileData::get(&variant)
+            .map_err(|e| anyhow::anyhow!(e))
+            .outcome(Outcome::LoadFailed, format!("failed to read {object_name}"))?;
+        objfile
+            .signature
+            .get_or_init(|| signature::check(&variant, &objfile.data).map_err(|e| format!("{e:#}")))
+            .clone()
+            .map_err(|e| anyhow::anyhow!(e))
+            .outcome(
+                Outcome::SignatureRejected,
+                format!("refusing to load {object_name}"),
+            )?;
+        Ok((variant, objfile))
+    }
+
+    /// Open the object and load it with the loader for its backend.
+    ///
+    /// The object is loaded without verifier log buffers first, allocating
+    /// them for every load adds up quickly with many devices and jobs. If
+    /// the load fails, the object is opened and loaded again with a log
+    /// buffer for each program to report why the verifier rejected it.
+    fn load_object(
+        path: &Path,
+        data: &[u8],
+        hid_id: u32,
+    ) -> Result<(Object, &'static dyn HidBPFLoader), BpfError> {
+        let open = || -> Result<OpenObject, BpfError> {
+            Ok(libbpf_rs::ObjectBuilder::default()
+                .name(objfile::filename(path))?
+                .open_memory(data)?)
+        };
+
+        let open_object = open()?;
+        let loader = get_bpf_loader(&open_object);
+        match loader.load(open_object, hid_id) {
+            Ok(object) => Ok((object, loader)),
+            Err(BpfError::LibBPFError { .. }) => {
+                log::debug!(target: "libbpf", "retrying {path:?} to get the verifier log");
+                let mut open_object = open()?;
+                let logs = VerifierLogs::attach(&mut open_object);
+                loader
+                    .load(open_object, hid_id)
+                    .map(|object| (object, loader))
+                    .map_err(|e| logs.into_error(e, &objfile::object_name(path)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Load, attach and pin the bpf.o file for the device. The file is read
+    /// once per invocation, each device gets its own instance of the object:
+    /// struct_ops objects are patched with the device's HID id before loading
+    /// and the udev properties are set in the loaded instance.
     pub fn load_programs(
         path: &Path,
         device: &hidudev::HidUdev,
         properties: &[hidudev::HidUdevProperty],
-    ) -> Result<()> {
+    ) -> Result<(), LoadError> {
+        let (variant, objfile) = HidBPF::read_objfile(path)?;
+        let path = &variant;
         log::debug!(target: "libbpf", "loading BPF object at {:?}", path.display());
 
-        let mut obj_builder = libbpf_rs::ObjectBuilder::default();
-        let open_object = obj_builder.open_file(path)?;
-
-        let loader = get_bpf_loader(&open_object);
-
-        let mut object = loader.load(open_object, device)?;
-        let object_name = path.file_stem().unwrap().to_str().unwrap();
+        let object_name = &objfile::object_name(path);
+        let (mut object, loader) =
+            HidBPF::load_object(path, &objfile.data, device.id()).map_err(|e| LoadError {
+                outcome: e.load_outcome(),
+                error: anyhow::Error::from(e).context(format!("failed to load {object_name}")),
+            })?;
 
         loader
             .inject_udev_properties(&mut object, device, properties)
-            .context(format!("couldn't set udev properties on {object_name}"))?;
+            .outcome(
+                Outcome::LoadFailed,
+                format!("couldn't set udev properties on {object_name}"),
+            )?;
 
         /*
          * if there is a "probe" syscall, execute it and
          * check for the return value: if not 0, then ignore
          * this bpf.o file
          */
-        loader
-            .probe(&object, device)
-            .context(format!("probe() of {object_name} failed"))?;
+        loader.probe(&object, device).outcome(
+            Outcome::ProbeRejected,
+            format!("probe() of {object_name} failed"),
+        )?;
 
         let bpffs_path = get_bpffs_path(&device.sysname(), object_name);
         loader
             .attach_and_pin(&mut object, device, &bpffs_path)
-            .context(format!("attach_and_pin() of {object_name} failed"))?;
+            .outcome(
+                Outcome::AttachFailed,
+                format!("attach_and_pin() of {object_name} failed"),
+            )?;
 
         if let Err(e) = HidBPF::pin_maps(&mut object, &bpffs_path) {
             let _ = std::fs::remove_dir_all(bpffs_path);
-            bail!(e);
+            return Err(e).outcome(
+                Outcome::PinFailed,
+                format!("failed to pin the maps of {object_name}"),
+            );
         };
 
         Ok(())
     }
+
+    /// Load the object into the kernel without attaching it. If a device
+    /// is given, the object is loaded for that device and its probe()
+    /// is run against that device.
+    ///
+    /// The object is unloaded again when this function returns.
+    pub fn load_test(
+        path: &Path,
+        device: Option<&hidudev::HidUdev>,
+        properties: &[hidudev::HidUdevProperty],
+    ) -> Result<()> {
+        log::debug!(target: "libbpf", "test loading BPF object at {:?}", path.display());
+
+        let data = objfile::read(path)?;
+        signature::check(path, &data)
+            .context(format!("refusing to load {}", objfile::object_name(path)))?;
+        let object_name = &objfile::object_name(path);
+
+        // HID id 0 is never a valid device but good enough to
+        // make the verifier happy
+        let (mut object, loader) =
+            HidBPF::load_object(path, &data, device.map(|d| d.id()).unwrap_or(0))
+                .context(format!("failed to load {object_name}"))?;
+
+        if let Some(device) = device {
+            loader
+                .inject_udev_properties(&mut object, device, properties)
+                .context(format!("couldn't set udev properties on {object_name}"))?;
+            loader
+                .probe(&object, device)
+                .context(format!("probe() of {object_name} failed"))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verifier_log_tail() {
+        let log = "func#0 @0\n\
+                   0: R1=ctx() R10=fp0\n\
+                   ; data = hid_bpf_get_data(hctx, 0, 4); @ foo.bpf.c:12\n\
+                   0: (b7) r2 = 0\n\
+                   ; return data[0]; @ foo.bpf.c:14\n\
+                   3: (71) r0 = *(u8 *)(r0 +0)\n\
+                   R0 invalid mem access 'mem_or_null'\n\
+                   processed 4 insns (limit 1000000) max_states_per_insn 0 total_states 0\n";
+        assert_eq!(
+            verifier_log_tail(log),
+            vec![
+                "; return data[0]; @ foo.bpf.c:14",
+                "3: (71) r0 = *(u8 *)(r0 +0)",
+                "R0 invalid mem access 'mem_or_null'",
+            ]
+        );
+
+        let log = "libbpf: failed to find BTF for extern\n";
+        assert_eq!(
+            verifier_log_tail(log),
+            vec!["libbpf: failed to find BTF for extern"]
+        );
+    }
 }
@@ -164,8 +164,153 @@ static inline void hid_bpf_printk_event(struct hid_bpf_ctx *hctx)
 		__uint(pid, (prod));	\
 	} COMBINE(_entry, __LINE__)
 
+/* The minimum kernel version the object works with, e.g.
+ * HID_BPF_MIN_KERNEL(6, 11). The object is not loaded on older kernels.
+ */
+#define HID_BPF_MIN_KERNEL(maj, min)	\
+	struct {			\
+		__uint(name, 1);	\
+		__uint(major, (maj));	\
+		__uint(minor, (min));	\
+	} COMBINE(_entry, __LINE__)
+
+/* The upstream status of the fix in this object, one of the values below.
+ * For HID_BPF_UPSTREAM_MERGED maj and min are the first kernel version with
+ * the fix, e.g. HID_BPF_UPSTREAM(HID_BPF_UPSTREAM_MERGED, 6, 12), otherwise
+ * they should be 0.
+ */
+#define HID_BPF_UPSTREAM_NONE		1
+#define HID_BPF_UPSTREAM_SUBMITTED	2
+#define HID_BPF_UPSTREAM_MERGED		3
+
+#define HID_BPF_UPSTREAM(s, maj, min)	\
+	struct {			\
+		__uint(name, 2);	\
+		__uint(status, (s));	\
+		__uint(major, (maj));	\
+		__uint(minor, (min));	\
+	} COMBINE(_entry, __LINE__)
+
+/* Declarative matches on the report descriptor, checked by udev-hid-bpf
+ * before the object is loaded so a probe() doesn't need to do it. The
+ * object is only loaded if the report descriptor has any of the
+ * HID_BPF_RDESC_SIZE() sizes (used in HID_BPF_CONFIG()), any of the
+ * HID_BPF_RDESC_SHA256() checksums and all of the HID_BPF_RDESC_BYTES()
+ * patterns (both used outside of HID_BPF_CONFIG()), e.g.
+ *
+ * HID_BPF_CONFIG(
+ *	HID_DEVICE(BUS_USB, HID_GROUP_GENERIC, 0x1234, 0x5678),
+ *	HID_BPF_RDESC_SIZE(232)
+ * );
+ * HID_BPF_RDESC_BYTES(177, 0xef);
+ *
+ * The offset of HID_BPF_RDESC_BYTES() must be an integer literal.
+ */
+#define HID_BPF_RDESC_SIZE(s)		\
+	struct {			\
+		__uint(name, 3);	\
+		__uint(size, (s));	\
+	} COMBINE(_entry, __LINE__)
+
+#define HID_BPF_RDESC_SHA256(hash)	\
+	const char COMBINE(_hid_bpf_rdesc_sha256_, __LINE__)[] SEC(".hid_bpf_config") = hash
+
+#define HID_BPF_RDESC_BYTES(offset, ...)	\
+	const __u8 COMBINE(COMBINE(_hid_bpf_rdesc_bytes_, offset), COMBINE(__, __LINE__))[] \
+		SEC(".hid_bpf_config") = { __VA_ARGS__ }
+
+/* Matches on the device beyond HID_DEVICE(), checked by udev-hid-bpf
+ * before the object is loaded. HID_BPF_USB_INTERFACE() is used in
+ * HID_BPF_CONFIG() and matches the bInterfaceNumber of the device's USB
+ * interface. The others are used outside of HID_BPF_CONFIG():
+ * HID_BPF_NAME() and HID_BPF_PHYS() are regular expressions that must match
+ * the whole HID_NAME or HID_PHYS, HID_BPF_UNIQ() must be equal to HID_UNIQ.
+ * Each may be used more than once, any of the values matches, e.g.
+ *
+ * HID_BPF_CONFIG(
+ *	HID_DEVICE(BUS_USB, HID_GROUP_GENERIC, 0x046D, 0xC52B),
+ *	HID_BPF_USB_INTERFACE(1)
+ * );
+ * HID_BPF_NAME("Logitech USB Receiver( Mouse)?");
+ */
+#define HID_BPF_USB_INTERFACE(n)	\
+	struct {			\
+		__uint(name, 4);	\
+		__uint(interface, (n));	\
+	} COMBINE(_entry, __LINE__)
+
+#define HID_BPF_NAME(regex)	\
+	const char COMBINE(_hid_bpf_match_name_, __LINE__)[] SEC(".hid_bpf_config") = regex
+
+#define HID_BPF_PHYS(regex)	\
+	const char COMBINE(_hid_bpf_match_phys_, __LINE__)[] SEC(".hid_bpf_config") = regex
+
+#define HID_BPF_UNIQ(uniq)	\
+	const char COMBINE(_hid_bpf_match_uniq_, __LINE__)[] SEC(".hid_bpf_config") = uniq
+
+/* Text metadata cannot be encoded in a type, so these are string
+ * constants in the .hid_bpf_config section next to HID_BPF_CONFIG().
+ * They are used outside of HID_BPF_CONFIG(), e.g.
+ *
+ * HID_BPF_DESCRIPTION("Fixes the stylus buttons of the Foo tablet");
+ * HID_BPF_UDEV_PROP(FOO_FIRMWARE_ID, "The firmware ID of the tablet");
+ * HID_BPF_CONFLICTS("Foo__Tablet-Pro");
+ *
+ * HID_BPF_UDEV_PROP() documents the UDEV_PROP_FOO_FIRMWARE_ID global.
+ * HID_BPF_CONFLICTS() takes the file name of another object without its
+ * priority prefix and suffix, at most one of the two objects is loaded
+ * for a device. It may be used more than once.
+ */
+#define HID_BPF_DESCRIPTION(text)	\
+	const char _hid_bpf_description[] SEC(".hid_bpf_config") = text
+
+#define HID_BPF_UDEV_PROP(prop, doc)	\
+	const char _hid_bpf_udev_prop_##prop[] SEC(".hid_bpf_config") = doc
+
+#define HID_BPF_CONFLICTS(object)	\
+	const char COMBINE(_hid_bpf_conflicts, __LINE__)[] SEC(".hid_bpf_config") = object
+
+/* The order udev-hid-bpf attaches the objects of a device in, the kernel
+ * runs the programs in that order. HID_BPF_ATTACH_PRIORITY() is used in
+ * HID_BPF_CONFIG(), objects with a lower priority are attached first, the
+ * default is 100. HID_BPF_ATTACH_BEFORE() and HID_BPF_ATTACH_AFTER() are
+ * used outside of HID_BPF_CONFIG(), take the file name of another object
+ * without its priority prefix and suffix and win over the priority, e.g.
+ *
+ * HID_BPF_CONFIG(
+ *	HID_DEVICE(BUS_USB, HID_GROUP_GENERIC, 0x1234, 0x5678),
+ *	HID_BPF_ATTACH_PRIORITY(10)
+ * );
+ * HID_BPF_ATTACH_BEFORE("userhacks__Foo-Remap");
+ */
+#define HID_BPF_ATTACH_PRIORITY(p)	\
+	struct {			\
+		__uint(name, 5);	\
+		__uint(priority, (p));	\
+	} COMBINE(_entry, __LINE__)
+
+#define HID_BPF_ATTACH_BEFORE(object)	\
+	const char COMBINE(_hid_bpf_attach_before, __LINE__)[] SEC(".hid_bpf_config") = object
+
+#define HID_BPF_ATTACH_AFTER(object)	\
+	const char COMBINE(_hid_bpf_attach_after, __LINE__)[] SEC(".hid_bpf_config") = object
+
+/* Files with the same name but a different priority prefix replace each
+ * other, only the loadable file with the highest prefix is loaded. A file
+ * with HID_BPF_STACKABLE() in its HID_BPF_CONFIG() extends the files with
+ * a lower prefix instead, e.g. a 0020-Foo__Bar.bpf.o add-on in testing for
+ * a 0010-Foo__Bar.bpf.o in stable. After it is loaded the loader continues
+ * with the next file, both are attached in that order.
+ */
+#define HID_BPF_STACKABLE()		\
+	struct {			\
+		__uint(name, 6);	\
+	} COMBINE(_entry, __LINE__)
+
 /* Macro magic below is to make HID_BPF_CONFIG() look like a function call that
- * we can pass multiple HID_DEVICE() invocations in.
+ * we can pass multiple HID_DEVICE() invocations in, optionally followed by
+ * HID_BPF_MIN_KERNEL(), HID_BPF_UPSTREAM(), HID_BPF_RDESC_SIZE(),
+ * HID_BPF_USB_INTERFACE(), HID_BPF_ATTACH_PRIORITY() and HID_BPF_STACKABLE().
  *
  * For up to 16 arguments, HID_BPF_CONFIG(one, two) resolves to
  *
@@ -0,0 +1,45 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+#include "vmlinux.h"
+#include "monitor.h"
+#include <bpf/bpf_helpers.h>
+#include <bpf/bpf_tracing.h>
+
+/* the device to monitor, set by userspace before loading */
+const volatile unsigned int hid_id = 0;
+
+struct {
+	__uint(type, BPF_MAP_TYPE_RINGBUF);
+	__uint(max_entries, 64 * sizeof(struct monitor_event));
+} events SEC(".maps");
+
+/* HID-BPF device_event programs run on a copy of the report, so when
+ * hid_input_report() returns, data still holds the report as the device
+ * sent it. The return value tells us whether the report made it through.
+ */
+SEC("fexit/hid_input_report")
+int BPF_PROG(monitor_input_report, struct hid_device *hdev, enum hid_report_type type,
+	     u8 *data, u32 size, int interrupt, int ret)
+{
+	struct monitor_event *event;
+	u32 len = size;
+
+	if (hdev->id != hid_id || type != HID_INPUT_REPORT)
+		return 0;
+
+	event = bpf_ringbuf_reserve(&events, sizeof(*event), 0);
+	if (!event)
+		return 0;
+
+	if (len > MONITOR_MAX_REPORT_SIZE)
+		len = MONITOR_MAX_REPORT_SIZE;
+
+	event->size = size;
+	event->retval = ret;
+	bpf_probe_read_kernel(event->data, len, data);
+	bpf_ringbuf_submit(event, 0);
+
+	return 0;
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,16 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+#ifndef __MONITOR_H
+#define __MONITOR_H
+
+#define MONITOR_MAX_REPORT_SIZE 4096
+
+/* One input report as received from the transport driver,
+ * i.e. before any HID-BPF program had a chance to modify it */
+struct monitor_event {
+	unsigned int size;	/* the size of the report, data is truncated to MONITOR_MAX_REPORT_SIZE */
+	int retval;		/* the return value of hid_input_report(), negative if the report was dropped */
+	unsigned char data[MONITOR_MAX_REPORT_SIZE];
+};
+
+#endif /* __MONITOR_H */
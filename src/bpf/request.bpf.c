@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+#include "vmlinux.h"
+#include "hid_bpf_helpers.h"
+#include "request.h"
+#include <bpf/bpf_helpers.h>
+
+SEC("syscall")
+int hw_request(struct request_args *args)
+{
+	struct hid_bpf_ctx *ctx;
+	const size_t size = args->size;
+
+	if (size > sizeof(args->data)) {
+		args->retval = -E2BIG;
+		return 0;
+	}
+
+	ctx = hid_bpf_allocate_context(args->hid);
+	if (!ctx) {
+		args->retval = -ENODEV;
+		return 0;
+	}
+
+	args->retval = hid_bpf_hw_request(ctx,
+					  args->data,
+					  size,
+					  args->type,
+					  args->request_type);
+
+	hid_bpf_release_context(ctx);
+
+	return 0;
+}
+
+SEC("syscall")
+int hw_output_report(struct request_args *args)
+{
+	struct hid_bpf_ctx *ctx;
+	const size_t size = args->size;
+
+	if (size > sizeof(args->data)) {
+		args->retval = -E2BIG;
+		return 0;
+	}
+
+	/* only available since kernel v6.11 */
+	if (!bpf_ksym_exists(hid_bpf_hw_output_report)) {
+		args->retval = -EOPNOTSUPP;
+		return 0;
+	}
+
+	ctx = hid_bpf_allocate_context(args->hid);
+	if (!ctx) {
+		args->retval = -ENODEV;
+		return 0;
+	}
+
+	args->retval = hid_bpf_hw_output_report(ctx, args->data, size);
+
+	hid_bpf_release_context(ctx);
+
+	return 0;
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,17 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+#ifndef __REQUEST_H
+#define __REQUEST_H
+
+#define REQUEST_MAX_SIZE 4096
+
+struct request_args {
+	unsigned char data[REQUEST_MAX_SIZE];
+	unsigned int hid;
+	unsigned int size;
+	unsigned int type;		/* enum hid_report_type, ignored for output reports */
+	unsigned int request_type;	/* enum hid_class_request, ignored for output reports */
+	int retval;
+};
+
+#endif /* __REQUEST_H */
@@ -12,8 +12,11 @@
 
 HID_BPF_CONFIG(
 	HID_DEVICE(BUS_USB, HID_GROUP_GENERIC, VID_BETOP_2185PC, PID_RAPTOR_MACH_2),
+	HID_BPF_RDESC_SIZE(232),
 );
 
+HID_BPF_RDESC_BYTES(177, 0xef); /* Logical Max of 239 */
+
 /*
  * For reference, this is the fixed report descriptor
  *
@@ -15,6 +15,9 @@ HID_BPF_CONFIG(
 	HID_DEVICE(BUS_USB, HID_GROUP_GENERIC, VID_HUION, PID_KEYDIAL_K20),
 );
 
+HID_BPF_DESCRIPTION("Maps the buttons and wheel of the Huion Keydial K20 to a tablet pad");
+HID_BPF_UDEV_PROP(HUION_FIRMWARE_ID, "The firmware ID of the device, e.g. HUION_T21h_230511");
+
 /* Filled in by udev-hid-bpf */
 char UDEV_PROP_HUION_FIRMWARE_ID[64];
 
@@ -1,16 +1,60 @@
 // SPDX-License-Identifier: GPL-2.0-only
 
 use crate::bpf;
-use crate::modalias::Modalias;
+use crate::index;
+use crate::journal;
+use crate::modalias::{self, DeviceProperties, KernelVersion, Modalias, ObjectInfo};
+use crate::objfile;
+use crate::report::{FileReport, GroupReport, Outcome};
 use log;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+/// The attach priority of objects without HID_BPF_ATTACH_PRIORITY
+pub const DEFAULT_ATTACH_PRIORITY: u32 = 100;
+/// Overrides the attach priority of objects, one "<name> <priority>" line
+/// per object with the name as in HID_BPF_ATTACH_BEFORE, e.g. "Huion__Dial-2 10"
+pub static ATTACH_PRIORITY_FILE: &str = "/etc/udev-hid-bpf/attach-priority";
+
+/// The (object name, priority) lines of an ATTACH_PRIORITY_FILE,
+/// a missing file has no lines
+pub fn read_attach_priorities(path: &Path) -> Vec<(String, u32)> {
+    let Ok(data) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    data.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let parsed = line
+                .split_once(char::is_whitespace)
+                .and_then(|(name, priority)| Some((name, priority.trim().parse().ok()?)));
+            if parsed.is_none() {
+                log::warn!("Ignoring invalid line '{line}' in {path:?}");
+            }
+            parsed.map(|(name, priority)| (String::from(name), priority))
+        })
+        .collect()
+}
+
 pub struct HidUdev {
     udev_device: udev::Device,
 }
 
+/// What the metadata of a bpf.o file is checked against before
+/// loading it, see HidUdev::metadata_rejection()
+#[derive(Default)]
+struct LoadContext {
+    kernel: Option<KernelVersion>,
+    /// The device's report descriptor, None if it could not be read
+    rdesc: Option<Vec<u8>>,
+    properties: DeviceProperties,
+    /// The (stem, metadata) of the files already loaded for the device,
+    /// by this or an earlier invocation
+    loaded: Vec<(String, ObjectInfo)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct HidUdevProperty {
     pub name: String,
@@ -54,7 +98,10 @@ impl HidUdev {
             .property_value("MODALIAS")
             .unwrap_or(std::ffi::OsStr::new("hid:empty"));
         let data = data.to_str().expect("modalias problem");
-        Modalias::from_str(data).unwrap()
+        Modalias::from_str(data).unwrap_or_else(|e| {
+            log::warn!("{e}, matching as any device");
+            Modalias::default()
+        })
     }
 
     pub fn sysname(&self) -> String {
@@ -65,6 +112,10 @@ impl HidUdev {
         String::from(self.udev_device.syspath().to_str().unwrap())
     }
 
+    pub fn report_descriptor(&self) -> std::io::Result<Vec<u8>> {
+        std::fs::read(PathBuf::from(self.syspath()).join("report_descriptor"))
+    }
+
     pub fn id(&self) -> u32 {
         let hid_sys = self.sysname();
         u32::from_str_radix(&hid_sys[15..], 16).unwrap()
@@ -94,6 +145,16 @@ impl HidUdev {
             .collect()
     }
 
+    /// The usbfs node of the USB device this HID device belongs to,
+    /// e.g. /dev/bus/usb/001/005, None for devices on other buses
+    pub fn usb_devnode(&self) -> Option<PathBuf> {
+        self.udev_device
+            .parent_with_subsystem_devtype("usb", "usb_device")
+            .ok()
+            .flatten()
+            .and_then(|parent| parent.devnode().map(PathBuf::from))
+    }
+
     pub fn is_ignored(&self) -> bool {
         self.udev_device
             .property_value("HID_BPF_IGNORE_DEVICE")
@@ -102,11 +163,23 @@ impl HidUdev {
 
     /// Find the given file name in the set of directories, returning a path
     /// to the first filename found. The directories are assumed in preference
-    /// order, first match wins.
+    /// order, first match wins. An absolute filename (e.g. as set by the local
+    /// hwdb of udev-hid-bpf install --hwdb) is used as-is.
+    ///
+    /// A compressed file or a bundle member with the given file name
+    /// is found too, see objfile::find_in_dir().
     fn find_first_matching_file(dirs: &[PathBuf], filename: &str) -> Option<PathBuf> {
-        dirs.iter()
-            .map(|d| d.join(filename))
-            .find(|path| path.is_file())
+        dirs.iter().find_map(|d| objfile::find_in_dir(d, filename))
+    }
+
+    /// The name a file is grouped by, i.e. the lowercase filename
+    /// without its priority prefix: 0010-Foo.bpf.o becomes foo.bpf.o
+    fn stem(path: &std::path::Path) -> String {
+        let filename = objfile::filename(path);
+        match filename.split_once('-') {
+            Some((_, rest)) => String::from(rest).to_lowercase(),
+            None => filename.to_lowercase(),
+        }
     }
 
     /// Given a set of paths that have filenames prefixed like 0010-foo.bpf.o, 0020-bar.bpf.o,
@@ -120,11 +193,7 @@ impl HidUdev {
         let mut ht: HashMap<String, Vec<PathBuf>> = HashMap::new();
 
         for path in paths.iter() {
-            let filename = String::from(path.file_name().unwrap().to_string_lossy());
-            let stem = match filename.split_once('-') {
-                Some((_, rest)) => String::from(rest).to_lowercase(),
-                None => String::from(&filename).to_lowercase(),
-            };
+            let stem = Self::stem(path);
             match ht.get_mut(&stem) {
                 Some(v) => v.push(PathBuf::from(path)),
                 None => {
@@ -137,8 +206,8 @@ impl HidUdev {
         // The list of values is reverse-dict sorted, so 30-foo comes first before 20-foo
         for v in ht.values_mut() {
             v.sort_by(|p1, p2| {
-                let p1: String = p1.file_name().unwrap().to_string_lossy().into();
-                let p2: String = p2.file_name().unwrap().to_string_lossy().into();
+                let p1 = objfile::filename(p1);
+                let p2 = objfile::filename(p2);
 
                 let p1 = p1.to_lowercase();
                 let p2 = p2.to_lowercase();
@@ -154,14 +223,78 @@ impl HidUdev {
         keys.iter().map(|k| ht.remove(k).unwrap()).collect()
     }
 
+    /// All files with the given file names in the set of directories, grouped
+    /// by stem and in the order they are tried, see load_bpf_files(). Each file is
+    /// followed by the files with the same name it shadows in directories with
+    /// a lower precedence, these are never tried.
+    pub fn find_candidates(filenames: &[String], bpf_dirs: &[PathBuf]) -> Vec<Vec<Candidate>> {
+        let mut filenames = filenames.to_vec();
+        filenames.sort();
+        filenames.dedup();
+
+        let mut copies: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for filename in &filenames {
+            let mut found: Vec<PathBuf> = bpf_dirs
+                .iter()
+                .filter_map(|d| objfile::find_in_dir(d, filename))
+                .collect();
+            // An absolute path is found in every directory
+            found.dedup();
+            if let Some((winner, shadowed)) = found.split_first() {
+                copies
+                    .entry(winner.clone())
+                    .or_default()
+                    .extend(shadowed.iter().filter(|p| *p != winner).cloned());
+            }
+        }
+
+        let winners: Vec<PathBuf> = copies.keys().cloned().collect();
+        Self::sort_by_stem(&winners)
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .flat_map(|winner| {
+                        let shadowed = copies.remove(&winner).unwrap_or_default();
+                        std::iter::once(Candidate {
+                            path: winner.clone(),
+                            shadowed_by: None,
+                        })
+                        .chain(shadowed.into_iter().map(move |path| {
+                            Candidate {
+                                path,
+                                shadowed_by: Some(winner.clone()),
+                            }
+                        }))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The file names of all files in the set of directories with the same
+    /// stem as the given file name, e.g. 0010-foo.bpf.o and 0020-foo.bpf.o for foo.bpf.o
+    pub fn find_stem_filenames(filename: &str, bpf_dirs: &[PathBuf]) -> Vec<String> {
+        let stem = Self::stem(std::path::Path::new(filename));
+        let mut filenames: Vec<String> = bpf_dirs
+            .iter()
+            .flat_map(|d| objfile::find_all(d).unwrap_or_default())
+            .filter(|p| Self::stem(p) == stem)
+            .map(|p| objfile::filename(&p))
+            .collect();
+        filenames.sort();
+        filenames.dedup();
+        filenames
+    }
+
     /// For each file find the first matching .bpf.o file within the set of directories.
     pub fn find_named_objfiles(filenames: &[String], bpf_dirs: &[PathBuf]) -> Vec<PathBuf> {
         filenames
             .iter()
-            .filter(|filename| filename.ends_with(".bpf.o"))
+            .filter(|filename| objfile::is_objfile(std::path::Path::new(filename)))
             .flat_map(|filename| {
                 let p = PathBuf::from(filename);
-                if p.exists() {
+                if objfile::exists(&p) {
                     Some(p)
                 } else {
                     Self::find_first_matching_file(bpf_dirs, filename)
@@ -189,26 +322,403 @@ impl HidUdev {
         Self::find_named_objfiles(&paths, bpf_dirs)
     }
 
+    /// The HID_NAME, HID_PHYS, HID_UNIQ and USB interface number of this device
+    pub fn device_properties(&self) -> DeviceProperties {
+        let property = |name: &str| {
+            self.udev_device
+                .property_value(name)
+                .and_then(|value| value.to_str())
+                .map(String::from)
+                .unwrap_or_default()
+        };
+        let usb_interface = self
+            .udev_device
+            .parent_with_subsystem_devtype("usb", "usb_interface")
+            .ok()
+            .flatten()
+            .and_then(|parent| {
+                parent
+                    .attribute_value("bInterfaceNumber")
+                    .and_then(|value| value.to_str())
+                    .and_then(|value| u32::from_str_radix(value.trim(), 16).ok())
+            });
+        DeviceProperties {
+            name: property("HID_NAME"),
+            phys: property("HID_PHYS"),
+            uniq: property("HID_UNIQ"),
+            usb_interface,
+        }
+    }
+
+    /// Why a file with the given stem and metadata must not be loaded for
+    /// this device, None if it can be loaded.
+    fn metadata_rejection(
+        stem: &str,
+        info: &ObjectInfo,
+        context: &LoadContext,
+    ) -> Option<(Outcome, String)> {
+        if let (Some(min_kernel), Some(kernel)) = (info.min_kernel, context.kernel) {
+            if kernel < min_kernel {
+                return Some((
+                    Outcome::KernelTooOld,
+                    format!("requires kernel {min_kernel}, running {kernel}"),
+                ));
+            }
+        }
+        if let Err(e) = info.device_match.check(&context.properties) {
+            return Some((Outcome::DeviceMismatch, e.to_string()));
+        }
+        match &context.rdesc {
+            Some(rdesc) => {
+                if let Err(e) = info.rdesc.check(rdesc) {
+                    return Some((Outcome::RdescMismatch, e.to_string()));
+                }
+            }
+            None if !info.rdesc.is_empty() => {
+                return Some((
+                    Outcome::RdescMismatch,
+                    String::from("report descriptor unavailable"),
+                ));
+            }
+            None => {}
+        }
+        context
+            .loaded
+            .iter()
+            .find(|(other_stem, other)| {
+                info.conflicts_with(other_stem) || other.conflicts_with(stem)
+            })
+            .map(|(other_stem, _)| {
+                (
+                    Outcome::Conflict,
+                    format!("conflicts with the loaded {other_stem}"),
+                )
+            })
+    }
+
+    /// The stem of a pinned object given its directory name in bpffs, e.g.
+    /// foo.bpf.o for 0010-Foo_bpf. Dots in the file name are lost in the
+    /// bpffs path, see bpf::get_bpffs_path(), so this is a best guess.
+    fn pinned_stem(name: &str) -> String {
+        let name = name.strip_suffix("_bpf").unwrap_or(name);
+        Self::stem(Path::new(&format!("{name}{}", objfile::OBJFILE_SUFFIX)))
+    }
+
+    /// The (stem, metadata) of the objects already pinned for this device,
+    /// e.g. by a separate invocation from a per-object udev rule. The
+    /// metadata of a pinned object is read from its file if that file is in
+    /// the same directory or bundle as any of the given paths, otherwise
+    /// only its stem is known.
+    fn pinned_objects(&self, paths: &[PathBuf]) -> Vec<(String, ObjectInfo)> {
+        let sysname = self.sysname();
+        let Ok(entries) = std::fs::read_dir(bpf::get_bpffs_path(&sysname, "")) else {
+            return Vec::new();
+        };
+        let mut dirs: Vec<&Path> = paths.iter().filter_map(|p| p.parent()).collect();
+        dirs.sort();
+        dirs.dedup();
+        let files: Vec<PathBuf> = dirs
+            .into_iter()
+            .flat_map(|dir| objfile::find_all(dir).unwrap_or_default())
+            .collect();
+        let pinned_name = |path: &PathBuf| {
+            PathBuf::from(bpf::get_bpffs_path(&sysname, &objfile::object_name(path)))
+                .file_name()
+                .map(|name| name.to_os_string())
+        };
+
+        entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| {
+                let name = entry.file_name();
+                match files
+                    .iter()
+                    .find(|f| pinned_name(f).as_ref() == Some(&name))
+                {
+                    Some(path) => (
+                        Self::stem(path),
+                        Self::object_info(path).unwrap_or_default(),
+                    ),
+                    None => (
+                        Self::pinned_stem(&name.to_string_lossy()),
+                        ObjectInfo::default(),
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    /// The metadata of the bpf.o file from its directory's index or the file
+    /// itself, read only once per invocation for all devices, see
+    /// bpf::ObjfileData::get(). An error if the file's metadata is invalid.
+    pub fn object_info(path: &Path) -> Result<ObjectInfo, String> {
+        match index::Index::lookup(path) {
+            Some(entry) => Ok(entry.info),
+            None => bpf::ObjfileData::get(path).and_then(|objfile| objfile.info.clone()),
+        }
+    }
+
+    /// Sort the stem groups into the order they are attached in. The kernel
+    /// runs the programs of a device in the order they were attached.
+    ///
+    /// A group is attached after all groups it must be attached after (its
+    /// HID_BPF_ATTACH_AFTER or the other group's HID_BPF_ATTACH_BEFORE),
+    /// otherwise groups with a lower attach priority come first and groups
+    /// with the same priority stay in stem order. The metadata of the first
+    /// file of each group decides. The priorities can be overridden by
+    /// name, see ATTACH_PRIORITY_FILE.
+    ///
+    /// Objects already attached by an earlier invocation are not taken
+    /// into account, the groups are always attached after them, see
+    /// pinned_order_conflicts().
+    fn sort_by_attach_order(
+        groups: Vec<Vec<(PathBuf, ObjectInfo)>>,
+        overrides: &[(String, u32)],
+    ) -> Vec<Vec<(PathBuf, ObjectInfo)>> {
+        let order: Vec<usize> = {
+            let stems: Vec<String> = groups.iter().map(|g| Self::stem(&g[0].0)).collect();
+            let infos: Vec<&ObjectInfo> = groups.iter().map(|g| &g[0].1).collect();
+            let priorities: Vec<u32> = std::iter::zip(&stems, &infos)
+                .map(|(stem, info)| Self::attach_priority(stem, info, overrides))
+                .collect();
+            // whether group a must be attached before group b
+            let before = |a: usize, b: usize| {
+                Self::must_attach_before((&stems[a], infos[a]), (&stems[b], infos[b]))
+            };
+
+            let mut order = Vec::new();
+            let mut remaining: Vec<usize> = (0..groups.len()).collect();
+            while !remaining.is_empty() {
+                let next = remaining
+                    .iter()
+                    .filter(|&&i| !remaining.iter().any(|&j| j != i && before(j, i)))
+                    .min_by_key(|&&i| (priorities[i], i))
+                    .copied()
+                    .unwrap_or_else(|| {
+                        let cycle: Vec<&String> = remaining.iter().map(|&i| &stems[i]).collect();
+                        log::warn!("Cyclic attach order between {cycle:?}, using priorities only");
+                        *remaining
+                            .iter()
+                            .min_by_key(|&&i| (priorities[i], i))
+                            .unwrap()
+                    });
+                remaining.retain(|&i| i != next);
+                order.push(next);
+            }
+            order
+        };
+
+        let mut groups: Vec<Option<Vec<(PathBuf, ObjectInfo)>>> =
+            groups.into_iter().map(Some).collect();
+        order.into_iter().filter_map(|i| groups[i].take()).collect()
+    }
+
+    /// The attach priority of the object with the given stem and metadata
+    fn attach_priority(stem: &str, info: &ObjectInfo, overrides: &[(String, u32)]) -> u32 {
+        overrides
+            .iter()
+            .find(|(name, _)| modalias::names_stem(std::slice::from_ref(name), stem))
+            .map(|(_, priority)| *priority)
+            .or(info.attach_priority)
+            .unwrap_or(DEFAULT_ATTACH_PRIORITY)
+    }
+
+    /// Whether object a must be attached before object b because of a's
+    /// HID_BPF_ATTACH_BEFORE or b's HID_BPF_ATTACH_AFTER
+    fn must_attach_before(a: (&str, &ObjectInfo), b: (&str, &ObjectInfo)) -> bool {
+        modalias::names_stem(&a.1.attach_before, b.0)
+            || modalias::names_stem(&b.1.attach_after, a.0)
+    }
+
+    /// The (group stem, pinned stem) of each group that should be attached
+    /// before an object already pinned for the device, by its attach
+    /// priority or HID_BPF_ATTACH_BEFORE/AFTER. Such a group is attached
+    /// after the pinned object anyway, e.g. when the per-object udev rules
+    /// written by `install` load one object per invocation.
+    fn pinned_order_conflicts(
+        groups: &[Vec<(PathBuf, ObjectInfo)>],
+        pinned: &[(String, ObjectInfo)],
+        overrides: &[(String, u32)],
+    ) -> Vec<(String, String)> {
+        let mut conflicts = Vec::new();
+        for group in groups {
+            let stem = Self::stem(&group[0].0);
+            let info = &group[0].1;
+            let priority = Self::attach_priority(&stem, info, overrides);
+            for (pinned_stem, pinned_info) in pinned {
+                if *pinned_stem == stem {
+                    continue;
+                }
+                let first = Self::must_attach_before((&stem, info), (pinned_stem, pinned_info));
+                let after = Self::must_attach_before((pinned_stem, pinned_info), (&stem, info));
+                if first
+                    || (!after
+                        && priority < Self::attach_priority(pinned_stem, pinned_info, overrides))
+                {
+                    conflicts.push((stem.clone(), pinned_stem.clone()));
+                }
+            }
+        }
+        conflicts
+    }
+
     pub fn load_bpf_files(
         &self,
         paths: &[PathBuf],
         properties: &[HidUdevProperty],
-    ) -> std::io::Result<()> {
-        let sorted: Vec<Vec<PathBuf>> = Self::sort_by_stem(&paths);
-        // For each group in our vec of vecs, try to load them one-by-one.
-        // The first successful one terminates that group and we continue with the next.
-        for group in sorted {
-            for path in group {
-                match bpf::HidBPF::load_programs(&path, self, properties) {
-                    Ok(_) => {
-                        log::info!("Successfully loaded {path:?}");
+    ) -> Vec<GroupReport> {
+        // A legacy variant is loaded instead of its base object if the kernel
+        // only supports its backend, see bpf::HidBPF::load_programs()
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .filter(|path| objfile::variant_base(path).map_or(true, |base| !paths.contains(&base)))
+            .cloned()
+            .collect();
+        let groups: Vec<Vec<(PathBuf, ObjectInfo)>> = Self::sort_by_stem(&paths)
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .map(|path| {
+                        let info = Self::object_info(&path).unwrap_or_default();
+                        (path, info)
+                    })
+                    .collect()
+            })
+            .collect();
+        let overrides = read_attach_priorities(Path::new(ATTACH_PRIORITY_FILE));
+        let sorted = Self::sort_by_attach_order(groups, &overrides);
+        let mut context = LoadContext {
+            kernel: KernelVersion::running(),
+            rdesc: self
+                .report_descriptor()
+                .map_err(|e| log::warn!("Failed to read the report descriptor: {e}"))
+                .ok(),
+            properties: self.device_properties(),
+            loaded: self.pinned_objects(&paths),
+        };
+        for (stem, pinned) in Self::pinned_order_conflicts(&sorted, &context.loaded, &overrides) {
+            log::warn!(
+                "{stem} should be attached before {pinned} which is already attached, \
+                 use add --replace to attach them in order"
+            );
+        }
+        // For each group in our vec of vecs, try to load them one-by-one, in attach
+        // order. The first successful one terminates that group and we continue with
+        // the next. A stackable file extends the remaining files of the group instead
+        // of replacing them: it is loaded after the first of them that loads, so that
+        // it runs after the file it extends, and not at all if none of them loads.
+        // A file is skipped if it needs a newer kernel, does not match the device's
+        // properties or report descriptor or conflicts with a file loaded from an
+        // earlier group.
+        sorted
+            .into_iter()
+            .map(|group| {
+                let mut report = GroupReport {
+                    stem: Self::stem(&group[0].0),
+                    files: Vec::new(),
+                    winner: None,
+                    stacked: Vec::new(),
+                };
+                // The stackable files waiting for their base, highest prefix first
+                let mut addons: Vec<(PathBuf, ObjectInfo)> = Vec::new();
+                for (path, info) in group {
+                    let _fields = journal::push_fields(&[(
+                        "bpf_object",
+                        String::from(path.to_string_lossy()),
+                    )]);
+                    // Without valid metadata none of its checks can be done
+                    if let Err(e) = Self::object_info(&path) {
+                        log::warn!(
+                            bpf_stage = Outcome::InvalidMetadata.stage();
+                            "Not loading {:?}: {}", path, e
+                        );
+                        report.files.push(FileReport {
+                            path,
+                            outcome: Outcome::InvalidMetadata,
+                            error: Some(e),
+                        });
+                        continue;
+                    }
+                    if let Some((outcome, reason)) =
+                        Self::metadata_rejection(&report.stem, &info, &context)
+                    {
+                        log::info!("Not loading {path:?}: {reason}");
+                        report.files.push(FileReport {
+                            path,
+                            outcome,
+                            error: Some(reason),
+                        });
+                        continue;
+                    }
+                    if info.stackable {
+                        log::debug!("Loading {path:?} after the file it extends");
+                        addons.push((path, info));
+                        continue;
+                    }
+                    let file = self.load_file(&path, properties);
+                    let loaded = file.outcome == Outcome::Loaded;
+                    report.files.push(file);
+                    if loaded {
+                        context.loaded.push((report.stem.clone(), info));
+                        report.winner = Some(path);
                         break;
                     }
-                    Err(e) => log::warn!("Failed to load {:?}: {:?}", path, e),
-                };
+                }
+
+                // The add-on closest to the base is attached first
+                for (path, info) in addons.into_iter().rev() {
+                    let _fields = journal::push_fields(&[(
+                        "bpf_object",
+                        String::from(path.to_string_lossy()),
+                    )]);
+                    if report.winner.is_none() {
+                        let reason = String::from("none of the files it extends was loaded");
+                        log::info!("Not loading {path:?}: {reason}");
+                        report.files.push(FileReport {
+                            path,
+                            outcome: Outcome::BaseNotLoaded,
+                            error: Some(reason),
+                        });
+                        continue;
+                    }
+                    let file = self.load_file(&path, properties);
+                    if file.outcome == Outcome::Loaded {
+                        context.loaded.push((report.stem.clone(), info));
+                        report.stacked.push(path);
+                    }
+                    report.files.push(file);
+                }
+                report
+            })
+            .collect()
+    }
+
+    /// Load, attach and pin the bpf.o file and log the outcome
+    fn load_file(&self, path: &Path, properties: &[HidUdevProperty]) -> FileReport {
+        match bpf::HidBPF::load_programs(path, self, properties) {
+            Ok(_) => {
+                log::info!("Successfully loaded {path:?}");
+                FileReport {
+                    path: PathBuf::from(path),
+                    outcome: Outcome::Loaded,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                log::warn!(
+                    bpf_stage = e.outcome.stage(),
+                    errno = e.errno();
+                    "Failed to load {:?}: {}", path, e
+                );
+                FileReport {
+                    path: PathBuf::from(path),
+                    outcome: e.outcome,
+                    error: Some(e.to_string()),
+                }
             }
         }
-        Ok(())
     }
 
     pub fn remove_bpf_objects(&self) -> std::io::Result<()> {
@@ -222,6 +732,14 @@ impl HidUdev {
     }
 }
 
+/// A file that may be loaded for a device, see HidUdev::find_candidates()
+#[derive(Debug, PartialEq)]
+pub struct Candidate {
+    pub path: PathBuf,
+    /// The file with the same name in a directory with a higher precedence
+    pub shadowed_by: Option<PathBuf>,
+}
+
 impl From<&HidUdevProperty> for HidUdevProperty {
     fn from(p: &HidUdevProperty) -> HidUdevProperty {
         HidUdevProperty {
@@ -314,6 +832,58 @@ mod tests {
             .for_each(|(objfile, exp)| assert!(&objfile == &exp, "{objfile:?} == {exp:?}"));
     }
 
+    #[test]
+    fn test_bpf_candidates() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let base_path = &tmpdir.path();
+        let usr_local = base_path.join("usr/local/lib/firmware");
+        let usr = base_path.join("lib/firmware");
+        std::fs::create_dir_all(&usr_local).unwrap();
+        std::fs::create_dir_all(&usr).unwrap();
+
+        for p in [
+            usr_local.join("0020-one.bpf.o"),
+            usr.join("0020-one.bpf.o"),
+            usr.join("0010-one.bpf.o"),
+            usr.join("0010-two.bpf.o"),
+        ] {
+            File::create(p).unwrap();
+        }
+        let dirs = [usr_local.clone(), usr.clone()];
+
+        let filenames = HidUdev::find_stem_filenames("one.bpf.o", &dirs);
+        assert_eq!(filenames, vec!["0010-one.bpf.o", "0020-one.bpf.o"]);
+
+        let candidates = HidUdev::find_candidates(&filenames, &dirs);
+        assert_eq!(
+            candidates,
+            vec![vec![
+                Candidate {
+                    path: usr_local.join("0020-one.bpf.o"),
+                    shadowed_by: None,
+                },
+                Candidate {
+                    path: usr.join("0020-one.bpf.o"),
+                    shadowed_by: Some(usr_local.join("0020-one.bpf.o")),
+                },
+                Candidate {
+                    path: usr.join("0010-one.bpf.o"),
+                    shadowed_by: None,
+                },
+            ]]
+        );
+
+        let filenames: Vec<String> = vec!["0010-two.bpf.o".into(), "0010-three.bpf.o".into()];
+        let candidates = HidUdev::find_candidates(&filenames, &dirs);
+        assert_eq!(
+            candidates,
+            vec![vec![Candidate {
+                path: usr.join("0010-two.bpf.o"),
+                shadowed_by: None,
+            }]]
+        );
+    }
+
     #[test]
     fn test_bpf_stem_sorting() {
         let tmpdir = tempfile::tempdir().unwrap();
@@ -353,4 +923,203 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_metadata_rejection() {
+        let kernel = Some(KernelVersion {
+            major: 6,
+            minor: 11,
+        });
+        let mut context = LoadContext {
+            kernel,
+            ..Default::default()
+        };
+        let mut info = ObjectInfo {
+            min_kernel: Some(KernelVersion {
+                major: 6,
+                minor: 12,
+            }),
+            ..Default::default()
+        };
+        let rejection = HidUdev::metadata_rejection("foo.bpf.o", &info, &context);
+        assert_eq!(rejection.map(|r| r.0), Some(Outcome::KernelTooOld));
+        // unknown kernel version, let the kernel decide
+        context.kernel = None;
+        assert!(HidUdev::metadata_rejection("foo.bpf.o", &info, &context).is_none());
+        context.kernel = kernel;
+
+        info.min_kernel = kernel;
+        assert!(HidUdev::metadata_rejection("foo.bpf.o", &info, &context).is_none());
+
+        info.rdesc.sizes = vec![3];
+        context.rdesc = Some(vec![0x05, 0x01, 0xc0]);
+        assert!(HidUdev::metadata_rejection("foo.bpf.o", &info, &context).is_none());
+        context.rdesc = Some(vec![0x05, 0x01]);
+        let rejection = HidUdev::metadata_rejection("foo.bpf.o", &info, &context);
+        assert_eq!(rejection.map(|r| r.0), Some(Outcome::RdescMismatch));
+        // the report descriptor could not be read
+        context.rdesc = None;
+        let rejection = HidUdev::metadata_rejection("foo.bpf.o", &info, &context);
+        assert_eq!(rejection.map(|r| r.0), Some(Outcome::RdescMismatch));
+        info.rdesc = Default::default();
+
+        info.device_match.usb_interfaces = vec![1];
+        context.properties.usb_interface = Some(0);
+        let rejection = HidUdev::metadata_rejection("foo.bpf.o", &info, &context);
+        assert_eq!(rejection.map(|r| r.0), Some(Outcome::DeviceMismatch));
+        context.properties.usb_interface = Some(1);
+        assert!(HidUdev::metadata_rejection("foo.bpf.o", &info, &context).is_none());
+
+        context.loaded = vec![(String::from("bar.bpf.o"), ObjectInfo::default())];
+        assert!(HidUdev::metadata_rejection("foo.bpf.o", &info, &context).is_none());
+
+        info.conflicts = vec![String::from("Bar")];
+        let rejection = HidUdev::metadata_rejection("foo.bpf.o", &info, &context);
+        assert_eq!(rejection.map(|r| r.0), Some(Outcome::Conflict));
+
+        // a conflict declared by the loaded file
+        info.conflicts = vec![String::from("Foo")];
+        context.loaded = vec![(String::from("bar.bpf.o"), info)];
+        let rejection = HidUdev::metadata_rejection("foo.bpf.o", &ObjectInfo::default(), &context);
+        assert_eq!(rejection.map(|r| r.0), Some(Outcome::Conflict));
+    }
+
+    #[test]
+    fn test_pinned_stem() {
+        assert_eq!(HidUdev::pinned_stem("0010-Foo_bpf"), "foo.bpf.o");
+        assert_eq!(
+            HidUdev::pinned_stem("0010-Huion__Dial-2_bpf"),
+            "huion__dial-2.bpf.o"
+        );
+        assert_eq!(HidUdev::pinned_stem("foo_bpf"), "foo.bpf.o");
+    }
+
+    #[test]
+    fn test_attach_order() {
+        let group = |path: &str, info: ObjectInfo| vec![(PathBuf::from(path), info)];
+        let stems = |groups: &Vec<Vec<(PathBuf, ObjectInfo)>>| -> Vec<String> {
+            groups.iter().map(|g| HidUdev::stem(&g[0].0)).collect()
+        };
+        let groups = vec![
+            group("0010-a.bpf.o", ObjectInfo::default()),
+            group(
+                "0010-b.bpf.o",
+                ObjectInfo {
+                    attach_priority: Some(10),
+                    ..Default::default()
+                },
+            ),
+            group("0010-c.bpf.o", ObjectInfo::default()),
+        ];
+        let sorted = HidUdev::sort_by_attach_order(groups.clone(), &[]);
+        assert_eq!(stems(&sorted), ["b.bpf.o", "a.bpf.o", "c.bpf.o"]);
+
+        let sorted = HidUdev::sort_by_attach_order(groups, &[(String::from("C"), 1)]);
+        assert_eq!(stems(&sorted), ["c.bpf.o", "b.bpf.o", "a.bpf.o"]);
+
+        // relations win over priorities
+        let groups = vec![
+            group(
+                "0010-a.bpf.o",
+                ObjectInfo {
+                    attach_priority: Some(1),
+                    attach_after: vec![String::from("C")],
+                    ..Default::default()
+                },
+            ),
+            group("0010-b.bpf.o", ObjectInfo::default()),
+            group(
+                "0010-c.bpf.o",
+                ObjectInfo {
+                    attach_before: vec![String::from("b")],
+                    ..Default::default()
+                },
+            ),
+        ];
+        let sorted = HidUdev::sort_by_attach_order(groups, &[]);
+        assert_eq!(stems(&sorted), ["c.bpf.o", "a.bpf.o", "b.bpf.o"]);
+
+        // a cycle falls back to the priorities
+        let groups = vec![
+            group(
+                "0010-a.bpf.o",
+                ObjectInfo {
+                    attach_after: vec![String::from("b")],
+                    ..Default::default()
+                },
+            ),
+            group(
+                "0010-b.bpf.o",
+                ObjectInfo {
+                    attach_after: vec![String::from("a")],
+                    ..Default::default()
+                },
+            ),
+        ];
+        let sorted = HidUdev::sort_by_attach_order(groups, &[]);
+        assert_eq!(stems(&sorted), ["a.bpf.o", "b.bpf.o"]);
+    }
+
+    #[test]
+    fn test_pinned_order_conflicts() {
+        let priority = |priority| ObjectInfo {
+            attach_priority: Some(priority),
+            ..Default::default()
+        };
+        let groups = vec![
+            vec![(PathBuf::from("0010-a.bpf.o"), priority(10))],
+            vec![(PathBuf::from("0010-b.bpf.o"), ObjectInfo::default())],
+        ];
+        let pinned = vec![(String::from("c.bpf.o"), ObjectInfo::default())];
+        assert_eq!(
+            HidUdev::pinned_order_conflicts(&groups, &pinned, &[]),
+            [(String::from("a.bpf.o"), String::from("c.bpf.o"))]
+        );
+        assert!(
+            HidUdev::pinned_order_conflicts(&groups, &pinned, &[(String::from("c"), 1)]).is_empty()
+        );
+
+        // the pinned object must be attached after b
+        let pinned = vec![(
+            String::from("c.bpf.o"),
+            ObjectInfo {
+                attach_after: vec![String::from("B")],
+                ..priority(200)
+            },
+        )];
+        assert_eq!(
+            HidUdev::pinned_order_conflicts(&groups, &pinned, &[]),
+            [
+                (String::from("a.bpf.o"), String::from("c.bpf.o")),
+                (String::from("b.bpf.o"), String::from("c.bpf.o"))
+            ]
+        );
+
+        // a pinned object with the same stem is the same object
+        let pinned = vec![(String::from("a.bpf.o"), priority(200))];
+        assert_eq!(
+            HidUdev::pinned_order_conflicts(&groups, &pinned, &[]),
+            [(String::from("b.bpf.o"), String::from("a.bpf.o"))]
+        );
+    }
+
+    #[test]
+    fn test_read_attach_priorities() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("attach-priority");
+        assert!(read_attach_priorities(&path).is_empty());
+
+        std::fs::write(
+            &path,
+            "# vendor fixes first\nHuion__Dial-2  10\n\nuserhack 200 # remap\ninvalid\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_attach_priorities(&path),
+            vec![
+                (String::from("Huion__Dial-2"), 10),
+                (String::from("userhack"), 200)
+            ]
+        );
+    }
 }
@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+use crate::hidudev::HidUdev;
+use crate::modalias::Bus;
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::os::fd::AsRawFd;
+
+const VID_HUION: u32 = 0x256c;
+
+/// The string descriptor with the firmware ID, e.g. "HUION_T21j_221221"
+const FIRMWARE_ID_INDEX: u8 = 201;
+/// The string descriptor with the tablet's parameters (logical maximums,
+/// resolution, ...). Reading it switches the tablet from firmware mode
+/// into vendor mode.
+const MAGIC_BYTES_INDEX: u8 = 200;
+const LANGID_EN_US: u16 = 0x0409;
+
+const USB_DIR_IN: u8 = 0x80;
+const USB_REQ_GET_DESCRIPTOR: u8 = 0x06;
+const USB_DT_STRING: u8 = 0x03;
+
+/// struct usbdevfs_ctrltransfer from linux/usbdevice_fs.h
+#[repr(C)]
+struct UsbdevfsCtrlTransfer {
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+    timeout: u32,
+    data: *mut libc::c_void,
+}
+
+/// Read a string descriptor as-is, including its length and type bytes
+fn get_string_descriptor(usb: &File, index: u8) -> Result<Vec<u8>> {
+    let mut buf = [0u8; 255];
+    let mut transfer = UsbdevfsCtrlTransfer {
+        request_type: USB_DIR_IN,
+        request: USB_REQ_GET_DESCRIPTOR,
+        value: (u16::from(USB_DT_STRING) << 8) | u16::from(index),
+        index: LANGID_EN_US,
+        length: buf.len() as u16,
+        timeout: 1000,
+        data: buf.as_mut_ptr().cast(),
+    };
+
+    // USBDEVFS_CONTROL, _IOWR('U', 0, struct usbdevfs_ctrltransfer)
+    let request = libc::_IOWR::<UsbdevfsCtrlTransfer>(b'U' as u32, 0);
+    let rc = unsafe { libc::ioctl(usb.as_raw_fd(), request, &mut transfer) };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error())
+            .context(format!("Failed to read string descriptor {index}"));
+    }
+
+    let len = (rc as usize).min(buf[0] as usize);
+    if len < 2 || buf[1] != USB_DT_STRING {
+        bail!("Invalid string descriptor {index}");
+    }
+    Ok(buf[..len].to_vec())
+}
+
+/// The text of a string descriptor, UTF-16LE after the length and type bytes
+fn descriptor_string(descriptor: &[u8]) -> String {
+    let units: Vec<u16> = descriptor
+        .get(2..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The udev properties the Huion BPF programs probe on:
+/// HUION_FIRMWARE_ID, e.g. "HUION_T21j_221221" and HUION_MAGIC_BYTES, the
+/// tablet's parameters descriptor in hex, e.g. "1403007d00204e00ff1fd8130306008004006308".
+///
+/// Other devices and interfaces that fail to return these descriptors
+/// have no such properties and return an empty list.
+pub fn firmware_properties(device: &HidUdev) -> Result<Vec<(&'static str, String)>> {
+    let modalias = device.modalias();
+    if modalias.bus != Bus::USB || modalias.vid != VID_HUION {
+        return Ok(Vec::new());
+    }
+    let Some(node) = device.usb_devnode() else {
+        return Ok(Vec::new());
+    };
+
+    let usb = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&node)
+        .context(format!("Failed to open {node:?}"))?;
+
+    // Read the firmware ID first, the magic bytes switch the tablet's mode.
+    // Not every Huion interface answers these, e.g. with EPIPE, such an
+    // interface simply has no properties.
+    let descriptors = get_string_descriptor(&usb, FIRMWARE_ID_INDEX).and_then(|firmware_id| {
+        get_string_descriptor(&usb, MAGIC_BYTES_INDEX).map(|magic_bytes| (firmware_id, magic_bytes))
+    });
+    let (firmware_id, magic_bytes) = match descriptors {
+        Ok(descriptors) => descriptors,
+        Err(e) => {
+            log::debug!("No firmware ID for {}: {e:#}", device.sysname());
+            return Ok(Vec::new());
+        }
+    };
+
+    Ok(vec![
+        ("HUION_FIRMWARE_ID", descriptor_string(&firmware_id)),
+        ("HUION_MAGIC_BYTES", hex_string(&magic_bytes)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_descriptor_string() {
+        let mut descriptor = vec![0x0c, USB_DT_STRING];
+        descriptor.extend("HUION".encode_utf16().flat_map(|c| c.to_le_bytes()));
+        assert_eq!(descriptor_string(&descriptor), "HUION");
+        assert_eq!(descriptor_string(&[0x02, USB_DT_STRING]), "");
+        assert_eq!(descriptor_string(&[]), "");
+    }
+
+    #[test]
+    fn test_hex_string() {
+        let magic = [0x14, 0x03, 0x00, 0x7d, 0x00, 0x20, 0x4e];
+        assert_eq!(hex_string(&magic), "1403007d00204e");
+    }
+}
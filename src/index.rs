@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! A per-directory index of the bpf.o files in that directory, so device
+//! matching doesn't need to open and parse the BTF of every object.
+//!
+//! The index is stored as INDEX_FILE in the directory it describes and is
+//! only ever written by the index command. Each entry records the mtime and
+//! size of its file, an entry whose file changed since is ignored until the
+//! index command is run again.
+
+use crate::modalias;
+use crate::objfile;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The index file name, relative to the directory it describes
+pub static INDEX_FILE: &str = ".hid-bpf-index.json";
+
+/// One bpf.o file in the index
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    /// The path relative to the directory, e.g. 0010-foo.bpf.o or
+    /// hid-bpf.bpf.bundle/0010-foo.bpf.o
+    pub path: PathBuf,
+    /// The modalias matches of this object, e.g. hid:b0003g*v0000256Cp0000006D
+    pub modaliases: Vec<String>,
+    /// The section names of the object's programs, e.g. struct_ops/hid_device_event
+    pub hooks: Vec<String>,
+    /// The version prefix of the file name, e.g. 10 for 0010-foo.bpf.o
+    pub priority: u32,
+    /// The description, minimum kernel, etc. of the object
+    #[serde(default)]
+    pub info: modalias::ObjectInfo,
+    mtime: u64,
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Index {
+    pub objects: Vec<IndexEntry>,
+}
+
+/// The mtime in nanoseconds and size of the file a bpf.o file is stored in,
+/// i.e. the bundle for a bundle member
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path)
+        .or_else(|_| std::fs::metadata(path.parent().unwrap_or(path)))
+        .ok()?;
+    let mtime = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_nanos() as u64;
+    Some((mtime, metadata.len()))
+}
+
+/// The version prefix of a file name, 0 if there is none
+fn priority(path: &Path) -> u32 {
+    objfile::filename(path)
+        .split_once('-')
+        .and_then(|(prefix, _)| prefix.parse().ok())
+        .unwrap_or(0)
+}
+
+/// The prefix of the sysnames of devices this modalias matches, e.g.
+/// 0003:256C:006D. for hid:b0003g*v0000256Cp0000006D. Matches with
+/// a wildcard bus, vid or pid have no such prefix.
+pub fn sysname_prefix(modalias: &str) -> Option<String> {
+    let modalias: modalias::Modalias = modalias.parse().ok()?;
+    if modalias.bus == modalias::Bus::Any || modalias.vid == 0 || modalias.pid == 0 {
+        return None;
+    }
+    Some(format!(
+        "{:04X}:{:04X}:{:04X}.",
+        modalias.bus, modalias.vid, modalias.pid
+    ))
+}
+
+impl IndexEntry {
+    /// Create the entry for the bpf.o file at dir/path
+    fn new(dir: &Path, path: &Path) -> Result<Self> {
+        let fullpath = dir.join(path);
+        let filename = objfile::filename(&fullpath);
+        let data = objfile::read(&fullpath)?;
+
+        let btf = libbpf_rs::btf::Btf::from_raw(&filename, &data)
+            .context(format!("Failed to read BPF from {fullpath:?}"))?;
+        let metadata = btf
+            .as_ref()
+            .and_then(|btf| modalias::Metadata::from_btf(btf, &data));
+        let modaliases = metadata
+            .as_ref()
+            .map(|metadata| metadata.modaliases())
+            .transpose()
+            .context(format!("Invalid HID_DEVICE metadata in {fullpath:?}"))?
+            .unwrap_or_default()
+            .iter()
+            .map(|modalias| modalias.to_string())
+            .collect();
+        let info = metadata
+            .map(|metadata| metadata.info())
+            .transpose()
+            .context(format!("Invalid HID_BPF_CONFIG metadata in {fullpath:?}"))?
+            .unwrap_or_default();
+
+        let mut obj_builder = libbpf_rs::ObjectBuilder::default();
+        let object = obj_builder
+            .name(&filename)?
+            .open_memory(&data)
+            .context(format!("Failed to open {fullpath:?}"))?;
+        let hooks = object
+            .progs_iter()
+            .map(|prog| prog.section().to_string())
+            .collect();
+
+        let (mtime, size) = file_stamp(&fullpath).unwrap_or_default();
+        Ok(IndexEntry {
+            path: PathBuf::from(path),
+            modaliases,
+            hooks,
+            priority: priority(path),
+            info,
+            mtime,
+            size,
+        })
+    }
+
+    fn is_current(&self, dir: &Path) -> bool {
+        file_stamp(&dir.join(&self.path)) == Some((self.mtime, self.size))
+    }
+
+    /// The prefixes of the sysnames of devices this object matches,
+    /// see sysname_prefix()
+    pub fn sysname_prefixes(&self) -> Vec<String> {
+        self.modaliases
+            .iter()
+            .filter_map(|m| sysname_prefix(m))
+            .collect()
+    }
+}
+
+impl Index {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(INDEX_FILE)
+    }
+
+    fn read(dir: &Path) -> Result<Self> {
+        let path = Self::path(dir);
+        let data = std::fs::read(&path).context(format!("Failed to read {path:?}"))?;
+        serde_json::from_slice(&data).context(format!("Invalid index {path:?}"))
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let path = Self::path(dir);
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize the index")?;
+        std::fs::write(&path, json).context(format!("Failed to write {path:?}"))
+    }
+
+    /// Bring the index up-to-date with the bpf.o files in the directory,
+    /// only files that were added or modified since are opened.
+    /// Returns true if the index changed.
+    pub fn update(&mut self, dir: &Path) -> Result<bool> {
+        let mut paths: Vec<PathBuf> = objfile::find_all(dir)?
+            .iter()
+            .filter_map(|p| p.strip_prefix(dir).ok())
+            .map(PathBuf::from)
+            .collect();
+        paths.sort();
+
+        let mut changed = paths.len() != self.objects.len();
+        let mut objects = Vec::new();
+        for path in paths {
+            match self
+                .objects
+                .iter()
+                .find(|e| e.path == path && e.is_current(dir))
+            {
+                Some(entry) => objects.push(entry.clone()),
+                None => {
+                    changed = true;
+                    match IndexEntry::new(dir, &path) {
+                        Ok(entry) => objects.push(entry),
+                        Err(e) => log::warn!("Not indexing {path:?}: {e:#}"),
+                    }
+                }
+            }
+        }
+        self.objects = objects;
+        Ok(changed)
+    }
+
+    /// Load the index of the directory as written by the index command,
+    /// None if the directory has no index. Entries may be stale, see lookup().
+    pub fn load(dir: &Path) -> Option<Self> {
+        match Self::read(dir) {
+            Ok(index) => Some(index),
+            Err(e) => {
+                if Self::path(dir).exists() {
+                    log::warn!("{e:#}, ignoring the index");
+                }
+                None
+            }
+        }
+    }
+
+    /// The index entry for the given bpf.o file, if the file's directory
+    /// (or its bundle's directory) has an index and the file did not change
+    /// since it was indexed. The index of each directory is read only once
+    /// per invocation.
+    pub fn lookup(path: &Path) -> Option<IndexEntry> {
+        static INDEXES: OnceLock<Mutex<HashMap<PathBuf, Option<Arc<Index>>>>> = OnceLock::new();
+        let dir = match path.parent()? {
+            p if p.is_file() => p.parent()?,
+            p => p,
+        };
+        if dir.as_os_str().is_empty() {
+            return None;
+        }
+        let relpath = path.strip_prefix(dir).ok()?;
+
+        let indexes = INDEXES.get_or_init(Default::default);
+        let cached = indexes.lock().unwrap().get(dir).cloned();
+        let index = match cached {
+            Some(index) => index,
+            None => {
+                // Read without holding the lock, another thread may
+                // have read it in the meantime, either is fine
+                let index = Self::load(dir).map(Arc::new);
+                indexes
+                    .lock()
+                    .unwrap()
+                    .entry(PathBuf::from(dir))
+                    .or_insert(index)
+                    .clone()
+            }
+        }?;
+
+        index
+            .objects
+            .iter()
+            .find(|e| e.path == relpath)
+            .filter(|e| e.is_current(dir))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sysname_prefix() {
+        assert_eq!(
+            sysname_prefix("hid:b0003g*v0000256Cp0000006D"),
+            Some(String::from("0003:256C:006D."))
+        );
+        assert_eq!(
+            sysname_prefix("hid:b0005g0001v0000045Ep00000B22"),
+            Some(String::from("0005:045E:0B22."))
+        );
+        assert_eq!(sysname_prefix("hid:b0003g*v*p*"), None);
+        assert_eq!(sysname_prefix("hid:b*g*v0000256Cp0000006D"), None);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = tmpdir.path();
+        let path = dir.join("0010-foo.bpf.o");
+        std::fs::write(&path, [1, 2, 3]).unwrap();
+
+        let (mtime, size) = file_stamp(&path).unwrap();
+        let index = Index {
+            objects: vec![IndexEntry {
+                path: PathBuf::from("0010-foo.bpf.o"),
+                modaliases: vec![String::from("hid:b0003g*v0000256Cp0000006D")],
+                hooks: vec![],
+                priority: 10,
+                info: modalias::ObjectInfo::default(),
+                mtime,
+                size,
+            }],
+        };
+        index.write(dir).unwrap();
+
+        let entry = Index::lookup(&path).unwrap();
+        assert_eq!(entry.sysname_prefixes(), vec!["0003:256C:006D."]);
+        assert!(Index::lookup(&dir.join("0020-foo.bpf.o")).is_none());
+
+        // A modified file is not looked up until it is indexed again
+        std::fs::write(&path, [1, 2, 3, 4]).unwrap();
+        assert!(Index::lookup(&path).is_none());
+    }
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(priority(Path::new("0010-foo.bpf.o")), 10);
+        assert_eq!(priority(Path::new("b.bpf.bundle/0030-foo.bpf.o.zst")), 30);
+        assert_eq!(priority(Path::new("foo.bpf.o")), 0);
+    }
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! Bounded parallelism for loading bpf.o files for many devices at once.
+//!
+//! The log messages of a job are held back while the job runs and logged
+//! in job order once it is done, so the messages of one device are never
+//! interleaved with those of another and appear in the same order no matter
+//! how many jobs run at once.
+
+use crate::journal;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
+use std::sync::Mutex;
+
+thread_local! {
+    static CAPTURED: RefCell<Option<Vec<CapturedRecord>>> = const { RefCell::new(None) };
+}
+
+/// A log record held back until its job is done
+struct CapturedRecord {
+    level: log::Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    message: String,
+    key_values: Vec<(String, String)>,
+    /// The journal fields of the job's thread, see journal::push_fields()
+    fields: Vec<(String, String)>,
+}
+
+impl CapturedRecord {
+    fn new(record: &log::Record) -> Self {
+        CapturedRecord {
+            level: record.level(),
+            target: String::from(record.target()),
+            module_path: record.module_path().map(String::from),
+            file: record.file().map(String::from),
+            line: record.line(),
+            message: record.args().to_string(),
+            key_values: journal::key_values(record),
+            fields: journal::fields(),
+        }
+    }
+
+    fn log(&self) {
+        let fields: Vec<(&str, String)> = self
+            .fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        let _fields = journal::push_fields(&fields);
+        let key_values: Vec<(&str, &str)> = self
+            .key_values
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        log::logger().log(
+            &log::Record::builder()
+                .args(format_args!("{}", self.message))
+                .level(self.level)
+                .target(&self.target)
+                .module_path(self.module_path.as_deref())
+                .file(self.file.as_deref())
+                .line(self.line)
+                .key_values(&key_values)
+                .build(),
+        );
+    }
+}
+
+/// Wraps the actual logger to hold back the records of running jobs
+struct JobLogger {
+    inner: Box<dyn log::Log>,
+}
+
+impl log::Log for JobLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let captured = CAPTURED.with(|c| match c.borrow_mut().as_mut() {
+            Some(records) => {
+                records.push(CapturedRecord::new(record));
+                true
+            }
+            None => false,
+        });
+        if !captured {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Install the logger as the global logger, wrapped so that the
+/// messages of jobs run by run() are logged in job order
+pub fn init_logger(
+    logger: Box<dyn log::Log>,
+    level: log::LevelFilter,
+) -> Result<(), log::SetLoggerError> {
+    log::set_boxed_logger(Box::new(JobLogger { inner: logger })).map(|()| log::set_max_level(level))
+}
+
+/// The number of jobs to run at once if not configured otherwise:
+/// the number of CPUs
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Call f for each item, in up to the given number of threads at once, and
+/// return the results in the order of the items. The log messages of each
+/// call are logged once the calls for all earlier items are done. If a call
+/// panics, the panic is passed on once the messages of all calls are logged.
+pub fn run<T, R, F>(items: Vec<T>, jobs: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let jobs = jobs.clamp(1, items.len().max(1));
+    if jobs == 1 {
+        return items.into_iter().map(f).collect();
+    }
+
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (queue, f) = (&queue, &f);
+            scope.spawn(move || loop {
+                let Some((idx, item)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                CAPTURED.with(|c| *c.borrow_mut() = Some(Vec::new()));
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| f(item)));
+                let records = CAPTURED.with(|c| c.borrow_mut().take()).unwrap_or_default();
+                if sender.send((idx, result, records)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut results = Vec::new();
+        let mut panicked = None;
+        let mut next = 0;
+        let mut done = BTreeMap::new();
+        for (idx, result, records) in receiver {
+            done.insert(idx, (result, records));
+            while let Some((result, records)) = done.remove(&next) {
+                next += 1;
+                records.iter().for_each(CapturedRecord::log);
+                match result {
+                    Ok(result) => results.push(result),
+                    Err(payload) => {
+                        panicked.get_or_insert(payload);
+                    }
+                }
+            }
+        }
+        if let Some(payload) = panicked {
+            std::panic::resume_unwind(payload);
+        }
+        results
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let items: Vec<u64> = (0..20).collect();
+        for jobs in [1, 4, 100] {
+            let results = run(items.clone(), jobs, |item| {
+                // finish the later items first
+                std::thread::sleep(std::time::Duration::from_millis(20 - item));
+                item * 2
+            });
+            assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<u64>>());
+        }
+
+        assert!(run(Vec::<u64>::new(), 4, |item| item).is_empty());
+    }
+
+    #[test]
+    fn test_run_panic() {
+        let finished = Mutex::new(Vec::new());
+        let result = std::panic::catch_unwind(|| {
+            run((0..10).collect(), 4, |item: u64| {
+                assert_ne!(item, 3);
+                finished.lock().unwrap().push(item);
+            })
+        });
+        assert!(result.is_err());
+        // the other jobs still run
+        let mut finished = finished.into_inner().unwrap();
+        finished.sort();
+        assert_eq!(finished, [0, 1, 2, 4, 5, 6, 7, 8, 9]);
+    }
+}
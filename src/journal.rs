@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+use log::kv::{self, VisitSource, VisitValue};
+use std::cell::RefCell;
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+static JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+thread_local! {
+    static FIELDS: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Removes the fields added by push_fields() when dropped
+pub struct FieldsGuard {
+    count: usize,
+}
+
+impl Drop for FieldsGuard {
+    fn drop(&mut self) {
+        FIELDS.with(|f| {
+            let mut fields = f.borrow_mut();
+            let len = fields.len().saturating_sub(self.count);
+            fields.truncate(len);
+        });
+    }
+}
+
+/// Add the given fields to all messages logged by this thread
+/// until the returned guard is dropped.
+pub fn push_fields(fields: &[(&str, String)]) -> FieldsGuard {
+    FIELDS.with(|f| {
+        f.borrow_mut()
+            .extend(fields.iter().map(|(k, v)| (field_name(k), v.clone())))
+    });
+    FieldsGuard {
+        count: fields.len(),
+    }
+}
+
+/// Journal field names may only contain uppercase letters,
+/// digits and underscores.
+fn field_name(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+fn append_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        // Multi-line values are serialized as <name>\n<le64 length><value>\n
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+fn priority(level: log::Level) -> &'static str {
+    match level {
+        log::Level::Error => "3",
+        log::Level::Warn => "4",
+        log::Level::Info => "6",
+        log::Level::Debug | log::Level::Trace => "7",
+    }
+}
+
+/// Collects a record's key-values as strings, skipping null values
+struct KeyValues(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for KeyValues {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        struct StringVisitor(Option<String>);
+
+        impl<'v> VisitValue<'v> for StringVisitor {
+            fn visit_any(&mut self, value: kv::Value) -> Result<(), kv::Error> {
+                self.0 = Some(value.to_string());
+                Ok(())
+            }
+
+            fn visit_null(&mut self) -> Result<(), kv::Error> {
+                self.0 = None;
+                Ok(())
+            }
+        }
+
+        let mut visitor = StringVisitor(None);
+        value.visit(&mut visitor)?;
+        if let Some(value) = visitor.0 {
+            self.0.push((String::from(key.as_str()), value));
+        }
+        Ok(())
+    }
+}
+
+/// The key-values of a record as strings, skipping null values
+pub fn key_values(record: &log::Record) -> Vec<(String, String)> {
+    let mut key_values = KeyValues(Vec::new());
+    let _ = record.key_values().visit(&mut key_values);
+    key_values.0
+}
+
+/// The fields added with push_fields() on this thread
+pub fn fields() -> Vec<(String, String)> {
+    FIELDS.with(|f| f.borrow().clone())
+}
+
+/// Send an entry that is too large for a single datagram. The native
+/// protocol accepts a sealed memfd holding the entry instead, passed
+/// as the only content of the datagram.
+fn send_memfd(socket: &UnixDatagram, buf: &[u8]) -> std::io::Result<()> {
+    let name = b"udev-hid-bpf-journal\0";
+    let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+    let fd = unsafe { libc::memfd_create(name.as_ptr() as *const libc::c_char, flags) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    file.write_all(buf)?;
+
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    addr.sun_path
+        .iter_mut()
+        .zip(JOURNAL_SOCKET.as_bytes())
+        .for_each(|(dst, src)| *dst = *src as libc::c_char);
+
+    let fd_size = std::mem::size_of::<RawFd>() as u32;
+    let control_len = unsafe { libc::CMSG_SPACE(fd_size) } as usize;
+    // u64 to keep the control buffer aligned for the cmsghdr
+    let mut control = vec![0u64; (control_len + 7) / 8];
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_un as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control_len as _;
+
+    let rc = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_size) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+        libc::sendmsg(socket.as_raw_fd(), &msg, 0)
+    };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A logger writing to the systemd journal using its native protocol,
+/// see https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+///
+/// In addition to the message every entry carries the fields added with
+/// push_fields() and the record's key-values, e.g.
+/// `log::warn!(bpf_stage = "probe"; "...")` adds BPF_STAGE=probe.
+pub struct JournalLogger {
+    socket: UnixDatagram,
+    modules: Vec<String>,
+    level: log::LevelFilter,
+}
+
+impl log::Log for JournalLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let target = metadata.target();
+        metadata.level() <= self.level
+            && self.modules.iter().any(|m| {
+                target == m
+                    || target
+                        .strip_prefix(m.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = record.args().to_string();
+        let mut buf = Vec::new();
+        append_field(&mut buf, "MESSAGE", message.trim());
+        append_field(&mut buf, "PRIORITY", priority(record.level()));
+        append_field(&mut buf, "SYSLOG_IDENTIFIER", "udev-hid-bpf");
+        if let Some(file) = record.file() {
+            append_field(&mut buf, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            append_field(&mut buf, "CODE_LINE", &line.to_string());
+        }
+        FIELDS.with(|f| {
+            f.borrow()
+                .iter()
+                .for_each(|(name, value)| append_field(&mut buf, name, value))
+        });
+        key_values(record)
+            .iter()
+            .for_each(|(key, value)| append_field(&mut buf, &field_name(key), value));
+
+        let sent = match self.socket.send_to(&buf, JOURNAL_SOCKET) {
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EMSGSIZE) | Some(libc::ENOBUFS)) => {
+                send_memfd(&self.socket, &buf)
+            }
+            result => result.map(|_| ()),
+        };
+        if sent.is_err() {
+            eprintln!("{}: {}", record.level(), message.trim());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Whether we should log to the journal rather than stderr: if our stderr
+/// is already connected to the journal (e.g. we're run by a systemd service)
+/// or if we're run by udev whose RUN programs have no useful stderr.
+pub fn should_log_to_journal() -> bool {
+    if !Path::new(JOURNAL_SOCKET).exists() {
+        return false;
+    }
+
+    let stderr_is_journal = std::env::var("JOURNAL_STREAM")
+        .ok()
+        .and_then(|s| {
+            let (dev, ino) = s.split_once(':')?;
+            Some((dev.parse::<u64>().ok()?, ino.parse::<u64>().ok()?))
+        })
+        .is_some_and(|(dev, ino)| {
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            let rc = unsafe { libc::fstat(std::io::stderr().as_raw_fd(), &mut stat) };
+            rc == 0 && stat.st_dev as u64 == dev && stat.st_ino as u64 == ino
+        });
+
+    // udev sets SEQNUM (and ACTION, DEVPATH, ...) for RUN programs
+    let run_by_udev = std::env::var_os("SEQNUM").is_some();
+
+    stderr_is_journal || run_by_udev
+}
+
+impl JournalLogger {
+    /// A JournalLogger for the given modules, see jobs::init_logger()
+    pub fn new(modules: &[&str], level: log::LevelFilter) -> std::io::Result<Self> {
+        Ok(JournalLogger {
+            socket: UnixDatagram::unbound()?,
+            modules: modules.iter().map(|m| String::from(*m)).collect(),
+            level,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_field() {
+        let mut buf = Vec::new();
+        append_field(&mut buf, "MESSAGE", "hello");
+        append_field(&mut buf, "BPF_STAGE", "probe");
+        assert_eq!(buf, b"MESSAGE=hello\nBPF_STAGE=probe\n");
+
+        let mut buf = Vec::new();
+        append_field(&mut buf, "MESSAGE", "a\nb");
+        assert_eq!(buf, b"MESSAGE\n\x03\x00\x00\x00\x00\x00\x00\x00a\nb\n");
+    }
+
+    #[test]
+    fn test_field_name() {
+        assert_eq!(field_name("bpf_stage"), "BPF_STAGE");
+        assert_eq!(field_name("HID_SYSNAME"), "HID_SYSNAME");
+        assert_eq!(field_name("hid-bpf.object"), "HID_BPF_OBJECT");
+    }
+
+    #[test]
+    fn test_push_fields() {
+        {
+            let _outer = push_fields(&[("hid_sysname", "0003:045E:07A5.000B".into())]);
+            {
+                let _inner = push_fields(&[("bpf_object", "0010-foo.bpf.o".into())]);
+                assert_eq!(fields().len(), 2);
+                assert_eq!(fields()[1].0, "BPF_OBJECT");
+            }
+            assert_eq!(
+                fields(),
+                vec![("HID_SYSNAME".into(), "0003:045E:07A5.000B".into())]
+            );
+        }
+        assert!(fields().is_empty());
+    }
+}
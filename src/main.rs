@@ -11,10 +11,25 @@ use std::process::ExitCode;
 
 pub mod bpf;
 pub mod hidudev;
+pub mod huion;
+pub mod index;
+pub mod jobs;
+pub mod journal;
+pub mod manifest;
 pub mod modalias;
+pub mod monitor;
+pub mod objfile;
+pub mod rdesc;
+pub mod report;
+pub mod request;
+pub mod signature;
+pub mod trace;
 
 static DEFAULT_BPF_DIRS: &str = env!("BPF_LOOKUP_DIRS");
 static BINDIR: &str = env!("MESON_BINDIR");
+static INSTALL_FWDIR: &str = "/etc/udev-hid-bpf/";
+static INSTALL_UDEVDIR: &str = "/etc/udev/rules.d";
+static INSTALL_HWDB: &str = "/etc/udev/hwdb.d/99-hid-bpf-local.hwdb";
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -28,6 +43,17 @@ struct Cli {
     /// Enable verbose output
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
+    /// Save the full verifier log of any BPF program rejected by the kernel to this file
+    #[arg(long)]
+    verifier_log: Option<PathBuf>,
+    /// Log to the systemd journal instead of stderr. This is the default
+    /// if invoked by udev or if stderr is connected to the journal.
+    #[arg(long, default_value_t = false)]
+    journal: bool,
+    /// Check the signatures of bpf.o files before loading them, overriding
+    /// the policy set in /etc/udev-hid-bpf/signature-policy
+    #[arg(long, value_enum)]
+    signature_policy: Option<signature::Policy>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -73,6 +99,10 @@ fn tuple_parse(s: &str) -> std::result::Result<hidudev::HidUdevProperty, clap::e
     hidudev::HidUdevProperty::try_from(s)
 }
 
+fn hex_byte_parse(s: &str) -> std::result::Result<u8, String> {
+    u8::from_str_radix(hex_without_prefix(s), 16).map_err(|_| format!("invalid hex byte: {s}"))
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Load BPF programs for a device. This command is typically invoked
@@ -115,6 +145,20 @@ enum Commands {
         /// the empty string (NAME="")
         #[arg(short, long, value_parser=tuple_parse)]
         property: Vec<hidudev::HidUdevProperty>,
+        /// Print the files tried for each device and their outcome as JSON,
+        /// implies --exit-codes
+        #[arg(long, default_value_t = false)]
+        json: bool,
+        /// Exit with a distinct code if there was nothing to do or loading
+        /// failed, see the man page. Otherwise these cases exit with 0 so
+        /// the udev rule that runs this command doesn't log a failure.
+        #[arg(long, default_value_t = false)]
+        exit_codes: bool,
+        /// Load the BPF programs for up to this many devices at once,
+        /// defaults to the number of CPUs. The programs of one device
+        /// are always loaded one after the other.
+        #[arg(short, long)]
+        jobs: Option<usize>,
     },
     /// Remove all BPF programs for a given device. This command is typically
     /// invoked from a udev rule on the "remove" action.
@@ -122,6 +166,10 @@ enum Commands {
         /// sysfs path to a device, e.g. /sys/bus/hid/devices/0003:045E:07A5.000B
         #[clap(num_args = 1..)]
         devpaths: Vec<PathBuf>,
+        /// Exit with a distinct code if there was nothing to remove,
+        /// see the man page
+        #[arg(long, default_value_t = false)]
+        exit_codes: bool,
     },
     /// List currently installed BPF programs
     ListBpfPrograms {
@@ -129,6 +177,31 @@ enum Commands {
         #[arg(short, long)]
         bpfdir: Option<PathBuf>,
     },
+    /// Create or update the index of the bpf.o files in each directory.
+    ///
+    /// The index maps the files to the devices they match so that matching
+    /// devices doesn't need to open every file. The index is only written by
+    /// this command, a file added or modified since is opened as if there was
+    /// no index until the index is updated again.
+    Index {
+        /// The directories to index, defaults to the built-in bpf.o directories
+        dirs: Vec<PathBuf>,
+    },
+    /// Show which bpf.o files are found for a file name or a device and which of
+    /// those are loaded.
+    ///
+    /// For each stem (the file name without its version prefix) the files are
+    /// listed in the order they are tried, highest version prefix first. A file
+    /// with the same name in a lookup directory with a lower precedence is shadowed
+    /// and never tried.
+    Which {
+        /// Folder to look at for bpf objects before the built-in directories
+        #[arg(short, long)]
+        bpfdir: Option<PathBuf>,
+        /// A file name, e.g. 0010-foo.bpf.o or foo.bpf.o for all versions of that file,
+        /// or the sysfs path to a device, e.g. /sys/bus/hid/devices/0003:045E:07A5.000B
+        target: String,
+    },
     /// List available devices
     ListDevices {},
     /// Inspect a bpf.o file
@@ -136,31 +209,172 @@ enum Commands {
         /// One or more paths to a bpf.o file
         paths: Vec<PathBuf>,
     },
-    /// Install one bpf.o file.
+    /// Bundle bpf.o files into a single file.
     ///
-    /// The file is installed into /etc/udev-hid-bpf/ with a corresponding udev rule
-    /// in /etc/udev/rules.d/. This command should be used for testing a single bpf.o file
+    /// The bundle holds the objects and an index of their modaliases and metadata,
+    /// used instead of reading each object to list them or find their devices.
+    /// A bundle in one of the bpf.o directories is searched for objects like the
+    /// directory itself, so it can replace the individual files, e.g. in an initramfs.
+    Bundle {
+        /// The bundle to write, its name must end in .bpf.bundle
+        #[arg(short, long)]
+        output: PathBuf,
+        /// One or more paths to a bpf.o file or a directory of bpf.o files
+        #[clap(num_args = 1..)]
+        paths: Vec<PathBuf>,
+    },
+    /// Install one or more bpf.o files.
+    ///
+    /// Each file is installed into /etc/udev-hid-bpf/ with a corresponding udev rule
+    /// in /etc/udev/rules.d/. This command should be used for testing bpf.o files
     /// and/or in the case where a proper install of udev-hid-bpf is not otherwise suitable.
     ///
+    /// All installed files are recorded in the manifest.json file in the same
+    /// directory, use the list-installed command to show them.
+    ///
     /// This command looks for an existing udev-hid-bpf executable in the configured prefix,
     /// that executable is referenced in the udev rule. Use the --install-exe argument
     /// to install the current executable in that prefix.
     Install {
-        /// Path to a bpf.o file
-        path: PathBuf,
+        /// One or more paths to a bpf.o file or a directory containing bpf.o files
+        #[clap(num_args = 1..)]
+        paths: Vec<PathBuf>,
         /// The prefix, converted to $prefix/bin. Defaults to the compiled-in prefix.
         #[arg(long)]
         prefix: Option<PathBuf>,
+        /// Install all files into this root directory instead of /, e.g. to stage
+        /// an install into an image. The paths written into the udev rules,
+        /// hwdb and manifest are the paths without this root directory.
+        /// Nothing on the host is reloaded or updated.
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// The directory to install the bpf.o files into
+        #[arg(long, default_value = INSTALL_FWDIR)]
+        bpf_dir: PathBuf,
+        /// The directory to install the udev rules into
+        #[arg(long, default_value = INSTALL_UDEVDIR)]
+        udev_rules_dir: PathBuf,
         /// Overwrite an existing file with the same name
         #[arg(long, default_value_t = false)]
         force: bool,
         /// Install the udev-hid-bpf executable at the given prefix (if not already installed)
         #[arg(long, default_value_t = false)]
         install_exe: bool,
+        /// Test-load each file into the running kernel before installing it,
+        /// see the verify command
+        #[arg(long, default_value_t = false)]
+        verify: bool,
+        /// Install local hwdb entries in /etc/udev/hwdb.d/99-hid-bpf-local.hwdb instead
+        /// of one udev rule per file. The files are then loaded by the udev-hid-bpf
+        /// udev rule and take part in the same priority-based selection as the
+        /// system-wide bpf.o files.
+        #[arg(long, default_value_t = false)]
+        hwdb: bool,
+        /// Sign each bpf.o file with this ed25519 private key (PKCS#8 PEM) and
+        /// install the signature next to it, see the --signature-policy option
+        #[arg(long)]
+        sign_key: Option<PathBuf>,
         /// Do everything except actually creating/installing target files and directories
         #[arg(long, default_value_t = false)]
         dry_run: bool,
     },
+    /// Test-load one or more bpf.o files into the running kernel without attaching them.
+    ///
+    /// Each file is loaded through the same loader as used by the add command
+    /// and the verifier output is printed if the kernel rejects the file.
+    /// For each currently connected device that matches the file's HID_DEVICE
+    /// entries, the file is loaded for that device and its probe is run
+    /// against that device.
+    Verify {
+        /// One or more paths to a bpf.o file
+        #[clap(num_args = 1..)]
+        paths: Vec<PathBuf>,
+        /// Provide an arbitrary NAME=VALUE pair to the BPF program,
+        /// see the add command.
+        #[arg(short, long, value_parser=tuple_parse)]
+        property: Vec<hidudev::HidUdevProperty>,
+    },
+    /// Print the bpf_printk() output of the BPF programs loaded for a device.
+    ///
+    /// The output is read from a tracefs instance that records the kernel stack
+    /// trace of each bpf_printk() call, a line is only printed if the stack trace
+    /// shows it was printed by one of the device's programs.
+    ///
+    /// Events printed with hid_bpf_printk_event() are decoded back into reports.
+    Trace {
+        /// The sysfs path to a device, e.g. /sys/bus/hid/devices/0003:045E:07A5.000B
+        devpath: PathBuf,
+        /// Annotate each report with the field values according to the device's
+        /// report descriptor
+        #[arg(long, default_value_t = false)]
+        annotate: bool,
+    },
+    /// Print the input reports of a device as read from its hidraw node,
+    /// i.e. after any HID-BPF program modified them.
+    ///
+    /// Each report is decoded with the device's report descriptor as
+    /// modified by any HID-BPF program.
+    Monitor {
+        /// The sysfs path to a device, e.g. /sys/bus/hid/devices/0003:045E:07A5.000B
+        devpath: PathBuf,
+        /// Also print each report as sent by the device before any HID-BPF program
+        /// ran and show which fields were modified. This attaches a tracing program
+        /// to the kernel's hid_input_report() for as long as the monitor runs.
+        #[arg(long, default_value_t = false)]
+        raw: bool,
+    },
+    /// Send a request to a device through HID-BPF and print the response.
+    ///
+    /// The request is issued by a bundled BPF program with the same
+    /// hid_bpf_hw_request() and hid_bpf_hw_output_report() kfuncs a HID-BPF
+    /// program would use, e.g. to test the feature report that switches
+    /// a device into a different mode before writing the program.
+    Request {
+        /// The sysfs path to a device, e.g. /sys/bus/hid/devices/0003:045E:07A5.000B
+        devpath: PathBuf,
+        /// The request to send
+        #[arg(value_enum)]
+        request: request::Request,
+        /// The report as hex bytes, e.g. 02 01 ff, starting with the report ID
+        /// if the device uses report IDs. For get-report only the report ID is needed.
+        #[clap(num_args = 1.., value_parser = hex_byte_parse)]
+        data: Vec<u8>,
+        /// The type of the report for get-report and set-report
+        #[arg(long, value_enum, default_value_t = request::ReportType::Feature)]
+        report_type: request::ReportType,
+        /// The maximum number of bytes to read for get-report
+        #[arg(long, default_value_t = 64)]
+        size: usize,
+    },
+    /// Print the udev properties some BPF programs probe on as KEY=value lines.
+    ///
+    /// This command is typically invoked as udev IMPORT{program}. For Huion
+    /// tablets it reads the firmware ID and the tablet's parameters from the
+    /// device's USB string descriptors and prints HUION_FIRMWARE_ID and
+    /// HUION_MAGIC_BYTES. Reading the parameters switches the tablet into
+    /// vendor mode. Nothing is printed for other devices.
+    FirmwareId {
+        /// The sysfs path to a device, e.g. /sys/bus/hid/devices/0003:256C:0066.000C
+        devpath: PathBuf,
+    },
+    /// List bpf.o files installed with the install command and
+    /// warn about installed files that were modified or deleted since.
+    ListInstalled {
+        /// The root directory the files were installed into
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// The directory the bpf.o files were installed into
+        #[arg(long, default_value = INSTALL_FWDIR)]
+        bpf_dir: PathBuf,
+    },
+    /// List the bpf objects attached to devices in the order they are
+    /// attached in, which is the order the kernel runs their programs in.
+    /// The order is inferred from the ids of the objects' links.
+    Status {
+        /// The sysfs paths to the devices, e.g. /sys/bus/hid/devices/0003:045E:07A5.000B.
+        /// Defaults to all devices with attached objects.
+        devpaths: Vec<PathBuf>,
+    },
 }
 
 fn default_bpf_dirs() -> Vec<PathBuf> {
@@ -172,40 +386,72 @@ fn cmd_add(
     objfiles: &[String],
     bpfdir: Option<PathBuf>,
     properties: &[hidudev::HidUdevProperty],
-) -> Result<()> {
+    jobs: usize,
+) -> Result<report::AddReport> {
     for syspath in devices {
         ensure!(syspath.exists(), "Invalid syspath {syspath:?}");
     }
 
     let target_bpf_dirs: Vec<PathBuf> = bpfdir.into_iter().chain(default_bpf_dirs()).collect();
     if objfiles.is_empty() {
+        // Objects installed with install --hwdb are referenced by their full path
         ensure!(
-            target_bpf_dirs.iter().any(|d| d.exists()),
+            target_bpf_dirs
+                .iter()
+                .chain([PathBuf::from(INSTALL_FWDIR)].iter())
+                .any(|d| d.exists()),
             "bpf directories {:?} don't exist, aborting",
             target_bpf_dirs
         );
     }
 
-    for syspath in devices {
-        let dev = hidudev::HidUdev::from_syspath(syspath)?;
-        if !dev.is_ignored() {
+    // Devices are independent of each other, the groups of one device
+    // are not: they are attached in order and may conflict with each other
+    // A device that cannot be opened, e.g. because it was just removed,
+    // does not affect the others
+    let devices = jobs::run(devices.iter().collect(), jobs, |syspath| {
+        let dev = match hidudev::HidUdev::from_syspath(syspath) {
+            Ok(dev) => dev,
+            Err(e) => {
+                log::error!("Failed to open device {syspath:?}: {e}");
+                return report::DeviceReport {
+                    sysname: sysname_from_syspath(syspath).unwrap_or_default(),
+                    syspath: syspath.clone(),
+                    ignored: false,
+                    groups: Vec::new(),
+                    error: Some(e.to_string()),
+                };
+            }
+        };
+        let _fields = journal::push_fields(&[
+            ("hid_sysname", dev.sysname()),
+            ("hid_modalias", dev.modalias().to_kernel_string()),
+        ]);
+        let groups = if !dev.is_ignored() {
             if objfiles.is_empty() {
                 let objfiles = dev.search_for_matching_objfiles(&target_bpf_dirs);
-                dev.load_bpf_files(&objfiles, properties)?;
+                dev.load_bpf_files(&objfiles, properties)
             } else {
-                let bpf_files = hidudev::HidUdev::find_named_objfiles(&objfiles, &target_bpf_dirs);
+                let bpf_files = hidudev::HidUdev::find_named_objfiles(objfiles, &target_bpf_dirs);
                 if bpf_files.is_empty() {
                     log::warn!("Unable to find any BPF programs for: {:?}", objfiles);
-                } else {
-                    dev.load_bpf_files(&bpf_files, properties)?;
                 }
+                dev.load_bpf_files(&bpf_files, properties)
             }
         } else {
             log::warn!("Device {syspath:?} has HID_BPF_IGNORE_DEVICE set, skipping");
+            Vec::new()
+        };
+        report::DeviceReport {
+            sysname: dev.sysname(),
+            syspath: syspath.clone(),
+            ignored: dev.is_ignored(),
+            groups,
+            error: None,
         }
-    }
+    });
 
-    Ok(())
+    Ok(report::AddReport { devices })
 }
 
 fn sysname_from_syspath(syspath: &PathBuf) -> std::io::Result<String> {
@@ -219,7 +465,9 @@ fn sysname_from_syspath(syspath: &PathBuf) -> std::io::Result<String> {
         .ok_or(std::io::Error::from_raw_os_error(libc::EINVAL))
 }
 
-fn cmd_remove(syspaths: &Vec<PathBuf>) -> Result<()> {
+/// Returns report::EXIT_NOTHING_TO_DO if none of the devices had any BPF programs loaded
+fn cmd_remove(syspaths: &Vec<PathBuf>) -> Result<u8> {
+    let mut removed = false;
     for syspath in syspaths {
         let sysname = match hidudev::HidUdev::from_syspath(syspath) {
             Ok(dev) => dev.sysname(),
@@ -228,123 +476,206 @@ fn cmd_remove(syspaths: &Vec<PathBuf>) -> Result<()> {
                 _ => return Err(e.into()),
             },
         };
+        let _fields = journal::push_fields(&[("hid_sysname", sysname.clone())]);
+        removed |= PathBuf::from(bpf::get_bpffs_path(&sysname, "")).exists();
         bpf::remove_bpf_objects(&sysname)?;
     }
-    Ok(())
+    Ok(if removed {
+        report::EXIT_SUCCESS
+    } else {
+        report::EXIT_NOTHING_TO_DO
+    })
 }
 
-fn find_bpfs(dir: &PathBuf) -> Result<Vec<PathBuf>> {
-    ensure!(dir.exists(), "File or directory {dir:?} does not exist");
+fn cmd_list_bpf_programs(bpfdir: Option<PathBuf>) -> Result<()> {
+    let dirs: Vec<PathBuf> = bpfdir.into_iter().chain(default_bpf_dirs()).collect();
+    let mut found = false;
+    for dir in &dirs {
+        // With an index or bundle index we can show the device
+        // matches without opening each file
+        let files: Vec<(PathBuf, Option<Vec<String>>, Option<modalias::ObjectInfo>)> =
+            match objfile::find_all(dir) {
+                Ok(files) => files
+                    .into_iter()
+                    .map(|f| {
+                        if let Some(entry) = index::Index::lookup(&f) {
+                            (f, Some(entry.modaliases), Some(entry.info))
+                        } else if let Some(entry) = objfile::bundle_entry(&f) {
+                            (f, Some(entry.modaliases), Some(entry.info))
+                        } else {
+                            let info = modalias::ObjectInfo::read(&f).ok();
+                            (f, None, info)
+                        }
+                    })
+                    .collect(),
+                Err(_) => continue,
+            };
+        if files.is_empty() {
+            continue;
+        }
+        found = true;
 
-    let metadata = dir.metadata().unwrap();
-    let result = if metadata.is_file() {
-        if dir.to_str().unwrap().ends_with(".bpf.o") {
-            return Ok(vec![dir.into()]);
+        println!(
+            "Showing available BPF files in {}:",
+            dir.as_path().to_str().unwrap()
+        );
+        for (file, modaliases, info) in files {
+            match modaliases {
+                Some(modaliases) => {
+                    println!(" {}: {}", file.to_str().unwrap(), modaliases.join(", "))
+                }
+                None => println!(" {}", file.to_str().unwrap()),
+            }
+            let Some(info) = info else {
+                continue;
+            };
+            if let Some(description) = info.description {
+                println!("    {description}");
+            }
+            let mut details = Vec::new();
+            if let Some(kernel) = info.min_kernel {
+                details.push(format!("requires kernel {kernel}"));
+            }
+            if let Some(upstream) = info.upstream {
+                details.push(upstream.to_string());
+            }
+            if !info.conflicts.is_empty() {
+                details.push(format!("conflicts with {}", info.conflicts.join(", ")));
+            }
+            if let Some(priority) = info.attach_priority {
+                details.push(format!("attach priority {priority}"));
+            }
+            if !info.attach_before.is_empty() {
+                details.push(format!("attached before {}", info.attach_before.join(", ")));
+            }
+            if !info.attach_after.is_empty() {
+                details.push(format!("attached after {}", info.attach_after.join(", ")));
+            }
+            if info.stackable {
+                details.push(String::from("stackable"));
+            }
+            if !details.is_empty() {
+                println!("    ({})", details.join("; "));
+            }
+            for prop in info.udev_properties {
+                println!("    {}: {}", prop.name, prop.doc);
+            }
         }
-        bail!("Not a bpf.o file");
-    } else {
-        std::fs::read_dir(dir)?
-            .flatten()
-            .flat_map(|f| find_bpfs(&f.path()))
-            .flatten()
-            .collect()
-    };
+    }
+
+    ensure!(found, "no BPF object file found in {dirs:?}");
 
-    Ok(result)
+    println!("Use udev-hid-bpf inspect <file> to obtain more information about a BPF object file.");
+    Ok(())
 }
 
-fn cmd_list_bpf_programs(bpfdir: Option<PathBuf>) -> Result<()> {
+fn cmd_which(target: &str, bpfdir: Option<PathBuf>) -> Result<()> {
     let dirs: Vec<PathBuf> = bpfdir.into_iter().chain(default_bpf_dirs()).collect();
-    let files = dirs
-        .iter()
-        .map(move |dir| (dir, find_bpfs(dir)))
-        .filter(|t| matches!(t, (_, Ok(_))))
-        .inspect(|t| {
-            let (dir, files) = t;
-            if !files.as_ref().unwrap().is_empty() {
-                println!(
-                    "Showing available BPF files in {}:",
-                    dir.as_path().to_str().unwrap()
-                );
-                files
-                    .iter()
-                    .flatten()
-                    .for_each(|f| println!(" {}", f.to_str().unwrap()));
+    println!("Lookup directories, highest precedence first:");
+    for dir in &dirs {
+        println!("  {}", dir.display());
+    }
+
+    let filenames = if target.starts_with("/sys/") {
+        let device = hidudev::HidUdev::from_syspath(std::path::Path::new(target))?;
+        let filenames = device.hid_bpf_properties();
+        println!(
+            "Files listed in the HID_BPF_ udev properties of {}:",
+            device.sysname()
+        );
+        for filename in &filenames {
+            println!("  {filename}");
+        }
+        filenames
+    } else {
+        hidudev::HidUdev::find_stem_filenames(target, &dirs)
+    };
+
+    let groups = hidudev::HidUdev::find_candidates(&filenames, &dirs);
+    ensure!(!groups.is_empty(), "No bpf.o files found for {target}");
+
+    for group in groups {
+        println!();
+        let mut tried = 0;
+        let mut stacks = false;
+        for candidate in group {
+            let path = candidate.path.display();
+            match candidate.shadowed_by {
+                Some(winner) => println!(
+                    "     {path}\n       shadowed by {}, never tried (directory precedence)",
+                    winner.display()
+                ),
+                None => {
+                    tried += 1;
+                    println!("  {tried}. {path}");
+                    if tried == 1 {
+                        println!("       tried first (highest version prefix)");
+                    } else if stacks {
+                        println!(
+                            "       tried if the files above fail to load or are stackable (lower version prefix)"
+                        );
+                    } else {
+                        println!(
+                            "       tried if the files above fail to load (lower version prefix)"
+                        );
+                    }
+                    let stackable = match hidudev::HidUdev::object_info(&candidate.path) {
+                        Ok(info) => info.stackable,
+                        Err(e) => {
+                            println!("       invalid metadata, never loaded: {e}");
+                            false
+                        }
+                    };
+                    if stackable {
+                        println!(
+                            "       stackable, loaded after the first of the files below that loads"
+                        );
+                    }
+                    stacks = stackable;
+                }
             }
-        })
-        .flat_map(move |t| t.1.into_iter())
-        .flatten()
-        .collect::<Vec<PathBuf>>();
+        }
+    }
 
-    ensure!(!files.is_empty(), "no BPF object file found in {dirs:?}");
+    Ok(())
+}
 
-    println!("Use udev-hid-bpf inspect <file> to obtain more information about a BPF object file.");
+/// Create or update the index of each directory, see index.rs
+fn cmd_index(dirs: &[PathBuf]) -> Result<()> {
+    for dir in dirs {
+        let mut index = index::Index::load(dir).unwrap_or_default();
+        index.update(dir)?;
+        index.write(dir)?;
+        println!(
+            "Indexed {} bpf.o files in {}",
+            index.objects.len(),
+            index::Index::path(dir).display()
+        );
+    }
     Ok(())
 }
 
 fn cmd_list_devices() -> Result<()> {
-    let re = Regex::new(r"hid:b([A-Z0-9]{4})g([A-Z0-9]{4})v0000([A-Z0-9]{4})p0000([A-Z0-9]{4})")
-        .unwrap();
-
     println!("devices:");
     // We use this path because it looks nicer than the true device path in /sys/devices/pci...
     for entry in std::fs::read_dir("/sys/bus/hid/devices")? {
         let syspath = entry.unwrap().path();
         let device = udev::Device::from_syspath(&syspath)?;
         let name = device.property_value("HID_NAME").unwrap().to_str().unwrap();
-        if let Some(Some(matches)) = device
+        if let Some(Ok(modalias)) = device
             .property_value("MODALIAS")
-            .map(|modalias| re.captures(modalias.to_str().unwrap()))
+            .and_then(|modalias| modalias.to_str())
+            .map(|modalias| modalias.parse::<modalias::Modalias>())
         {
-            let bus = matches.get(1).unwrap().as_str();
-            let group = matches.get(2).unwrap().as_str();
-            let vid = matches.get(3).unwrap().as_str();
-            let pid = matches.get(4).unwrap().as_str();
-
-            let bus = match bus {
-                "0001" => "BUS_PCI",
-                "0002" => "BUS_ISAPNP",
-                "0003" => "BUS_USB",
-                "0004" => "BUS_HIL",
-                "0005" => "BUS_BLUETOOTH",
-                "0006" => "BUS_VIRTUAL",
-                "0010" => "BUS_ISA",
-                "0011" => "BUS_I8042",
-                "0012" => "BUS_XTKBD",
-                "0013" => "BUS_RS232",
-                "0014" => "BUS_GAMEPORT",
-                "0015" => "BUS_PARPORT",
-                "0016" => "BUS_AMIGA",
-                "0017" => "BUS_ADB",
-                "0018" => "BUS_I2C",
-                "0019" => "BUS_HOST",
-                "001A" => "BUS_GSC",
-                "001B" => "BUS_ATARI",
-                "001C" => "BUS_SPI",
-                "001D" => "BUS_RMI",
-                "001E" => "BUS_CEC",
-                "001F" => "BUS_INTEL_ISHTP",
-                "0020" => "BUS_AMD_SFH",
-                _ => bus,
-            };
-
-            let group = match group {
-                "0001" => "HID_GROUP_GENERIC",
-                "0002" => "HID_GROUP_MULTITOUCH",
-                "0003" => "HID_GROUP_SENSOR_HUB",
-                "0004" => "HID_GROUP_MULTITOUCH_WIN_8",
-                "0100" => "HID_GROUP_RMI",
-                "0101" => "HID_GROUP_WACOM",
-                "0102" => "HID_GROUP_LOGITECH_DJ_DEVICE",
-                "0103" => "HID_GROUP_STEAM",
-                "0104" => "HID_GROUP_LOGITECH_27MHZ_DEVICE",
-                "0105" => "HID_GROUP_VIVALDI",
-                _ => group,
-            };
-
             println!("  -  syspath:      \"{}\"", syspath.to_str().unwrap());
             println!("     name:         \"{name}\"");
-            println!("     device entry: \"HID_DEVICE({bus}, {group}, 0x{vid}, 0x{pid})\"");
+            println!(
+                "     device entry: \"HID_DEVICE({}, {}, 0x{:04X}, 0x{:04X})\"",
+                modalias.bus.name(),
+                modalias.group.name(),
+                modalias.vid,
+                modalias.pid
+            );
         }
     }
     Ok(())
@@ -369,39 +700,63 @@ struct InspectionMap {
     name: String,
 }
 
+/// One of the variants of an object, see objfile::variants()
+#[derive(Serialize)]
+struct InspectionVariant {
+    filename: String,
+    backend: bpf::Backend,
+}
+
 #[derive(Serialize)]
 struct InspectionData {
     filename: String,
     devices: Vec<InspectionDevice>,
+    #[serde(flatten)]
+    info: modalias::ObjectInfo,
+    backend: bpf::Backend,
+    /// All variants of the object, including this file, if there is more than one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    variants: Vec<InspectionVariant>,
     programs: Vec<InspectionProgram>,
     maps: Vec<InspectionMap>,
 }
 
 fn inspect(path: &PathBuf) -> Result<InspectionData> {
-    ensure!(path.exists(), "Invalid bpf.o path {path:?}");
+    ensure!(objfile::exists(path), "Invalid bpf.o path {path:?}");
 
-    let btf = libbpf_rs::btf::Btf::from_path(path)
+    let filename = objfile::filename(path);
+    let data = objfile::read(path)?;
+    let btf = libbpf_rs::btf::Btf::from_raw(&filename, &data)
         .context(format!("Failed to read BPF from {:?}", path))?;
-    let metadata = modalias::Metadata::from_btf(&btf);
-    let devices: Vec<InspectionDevice> = metadata
-        .and_then(|metadata| {
-            Some(
-                metadata
-                    .modaliases()
-                    .map(|modalias| InspectionDevice {
-                        bus: format!("0x{:04X}", modalias.bus),
-                        group: format!("0x{:04X}", modalias.group),
-                        vid: format!("0x{:04X}", modalias.vid),
-                        pid: format!("0x{:04X}", modalias.pid),
-                    })
-                    .collect::<Vec<InspectionDevice>>(),
-            )
+    let metadata = btf
+        .as_ref()
+        .and_then(|btf| modalias::Metadata::from_btf(btf, &data));
+    let modaliases = metadata
+        .as_ref()
+        .map(|metadata| metadata.modaliases())
+        .transpose()
+        .context(format!("Invalid HID_DEVICE metadata in {path:?}"))?
+        .unwrap_or_default();
+    let info = metadata
+        .map(|metadata| metadata.info())
+        .transpose()
+        .context(format!("Invalid HID_BPF_CONFIG metadata in {path:?}"))?
+        .unwrap_or_default();
+    let devices: Vec<InspectionDevice> = modaliases
+        .iter()
+        .map(|modalias| InspectionDevice {
+            bus: format!("0x{:04X}", modalias.bus),
+            group: format!("0x{:04X}", modalias.group),
+            vid: format!("0x{:04X}", modalias.vid),
+            pid: format!("0x{:04X}", modalias.pid),
         })
-        .or(Some(Vec::new()))
-        .unwrap();
+        .collect();
 
     let mut obj_builder = libbpf_rs::ObjectBuilder::default();
-    let object = obj_builder.open_file(path.clone()).unwrap();
+    let object = obj_builder
+        .name(&filename)?
+        .open_memory(&data)
+        .context(format!("Failed to open {path:?}"))?;
     let programs: Vec<InspectionProgram> = object
         .progs_iter()
         .map(|prog| InspectionProgram {
@@ -417,9 +772,27 @@ fn inspect(path: &PathBuf) -> Result<InspectionData> {
         })
         .collect();
 
+    let backend = bpf::Backend::of(&object);
+    let base = objfile::variant_base(path).unwrap_or(path.clone());
+    let variants = match objfile::variants(&base) {
+        variants if variants.len() > 1 => variants
+            .iter()
+            .map(|variant| {
+                Ok(InspectionVariant {
+                    filename: objfile::filename(variant),
+                    backend: bpf::Backend::of_objfile(variant)?,
+                })
+            })
+            .collect::<Result<Vec<InspectionVariant>>>()?,
+        _ => Vec::new(),
+    };
+
     let data = InspectionData {
-        filename: String::from(path.file_name().unwrap().to_string_lossy()),
+        filename: filename.clone(),
         devices,
+        info,
+        backend,
+        variants,
         programs,
         maps,
     };
@@ -430,7 +803,14 @@ fn inspect(path: &PathBuf) -> Result<InspectionData> {
 fn cmd_inspect(paths: &[PathBuf]) -> Result<()> {
     let objects = paths
         .iter()
-        .map(|path| inspect(path))
+        .map(|path| match path.is_file() {
+            true => objfile::objfiles_in_file(path),
+            false => Ok(vec![path.clone()]),
+        })
+        .collect::<Result<Vec<Vec<PathBuf>>>>()?
+        .iter()
+        .flatten()
+        .map(inspect)
         .collect::<Result<Vec<InspectionData>>>()?;
     let json = serde_json::to_string_pretty(&objects).context("Failed to parse json")?;
     println!("{}", json);
@@ -442,6 +822,7 @@ fn write_udev_rule(
     bindir: &std::path::Path,
     target: &std::path::Path,
     devices: &[InspectionDevice],
+    device_match: &modalias::DeviceMatch,
 ) -> Result<()> {
     let header = r#"# This udev rule was generated by udev-hid-bpf install
 ACTION!="add|remove", GOTO="hid_bpf_end"
@@ -449,6 +830,21 @@ SUBSYSTEM!="hid", GOTO="hid_bpf_end"
 "#;
     let footer = r#"LABEL="hid_bpf_end""#;
     let bindir = bindir.to_string_lossy();
+    // Only single values can be matched by udev, anything else
+    // is left to the loader. So is a uniq that udev would treat as a
+    // glob or alternative or that would break the rule's quoting.
+    let mut add_match = String::new();
+    if let [interface] = device_match.usb_interfaces[..] {
+        add_match += &format!(r#", ATTRS{{bInterfaceNumber}}=="{interface:02x}""#);
+    }
+    if let [uniq] = &device_match.uniq[..] {
+        if !uniq
+            .chars()
+            .any(|c| matches!(c, '"' | '\\' | '*' | '?' | '[' | '|') || c.is_control())
+        {
+            add_match += &format!(r#", ENV{{HID_UNIQ}}=="{uniq}""#);
+        }
+    }
 
     writeln!(rulefile, "{}", header)?;
     devices.iter().for_each(|dev| {
@@ -467,14 +863,14 @@ SUBSYSTEM!="hid", GOTO="hid_bpf_end"
             target.file_name().unwrap().to_string_lossy()
         ).unwrap();
         for action in ["add", "remove"] {
-            let bpf_o = match action {
-                "add" => target.to_string_lossy().into_owned(),
-                "remove" => String::from(""),
+            let (bpf_o, extra_match) = match action {
+                "add" => (target.to_string_lossy().into_owned(), add_match.as_str()),
+                "remove" => (String::from(""), ""),
                 &_ => panic!("Unexpected action") // can't happen
             };
             writeln!(
                 rulefile,
-                r#"ACTION=="{action}",{kernel_match}, RUN{{program}}+="{bindir}/udev-hid-bpf {action} $sys$devpath {bpf_o}""#
+                r#"ACTION=="{action}",{kernel_match}{extra_match}, RUN{{program}}+="{bindir}/udev-hid-bpf {action} $sys$devpath {bpf_o}""#
             )
             .unwrap();
         }
@@ -485,88 +881,410 @@ SUBSYSTEM!="hid", GOTO="hid_bpf_end"
     Ok(())
 }
 
-fn cmd_install(
-    path: &PathBuf,
+/// Resolve the paths given to the install command into a list of bpf.o files.
+/// Directories are searched recursively, files found within directories that
+/// have no HID_DEVICE entries are skipped.
+fn objfiles_to_install(paths: &[PathBuf]) -> Result<Vec<(PathBuf, InspectionData)>> {
+    let mut objfiles: Vec<(PathBuf, InspectionData)> = Vec::new();
+
+    for path in paths {
+        if path.is_dir() {
+            let mut found = objfile::find_all(path)?;
+            found.sort();
+            for objfile in found {
+                let idata = inspect(&objfile)?;
+                if idata.devices.is_empty() {
+                    eprintln!("WARNING: Skipping {objfile:?}, it has no HID_DEVICE entries");
+                    continue;
+                }
+                objfiles.push((objfile, idata));
+            }
+        } else {
+            if !objfile::is_objfile(path) {
+                bail!("Expected a bpf.o file or a directory as argument, not {path:?}");
+            }
+            let idata = inspect(path)?;
+            if idata.devices.is_empty() {
+                bail!("{path:?} has no HID_DEVICE entries and must be manually attached");
+            }
+            objfiles.push((path.clone(), idata));
+        }
+    }
+
+    ensure!(!objfiles.is_empty(), "No bpf.o files found in {paths:?}");
+
+    for (idx, (path, idata)) in objfiles.iter().enumerate() {
+        if let Some((other, _)) = objfiles[..idx]
+            .iter()
+            .find(|(_, other)| other.filename == idata.filename)
+        {
+            bail!("{path:?} and {other:?} would be installed as the same file");
+        }
+    }
+
+    Ok(objfiles)
+}
+
+fn cmd_bundle(output: &std::path::Path, paths: &[PathBuf]) -> Result<()> {
+    ensure!(
+        output.to_string_lossy().ends_with(objfile::BUNDLE_SUFFIX),
+        "The bundle's file name must end in {}",
+        objfile::BUNDLE_SUFFIX
+    );
+
+    let mut objfiles: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        let mut found = objfile::find_all(path)?;
+        found.sort();
+        objfiles.extend(found);
+    }
+    ensure!(!objfiles.is_empty(), "No bpf.o files found in {paths:?}");
+
+    let mut objects: Vec<(String, Vec<String>, modalias::ObjectInfo, Vec<u8>)> = Vec::new();
+    for path in &objfiles {
+        let idata = inspect(path)?;
+        if let Some((other, _, _, _)) = objects
+            .iter()
+            .find(|(name, _, _, _)| *name == idata.filename)
+        {
+            bail!("{path:?} and {other} would be bundled as the same file");
+        }
+        let modaliases = idata.devices.iter().map(hwdb_modalias).collect();
+        objects.push((idata.filename, modaliases, idata.info, objfile::read(path)?));
+    }
+
+    objfile::Bundle::write(output, &objects)?;
+    println!("Bundled {} bpf.o files into {output:?}", objects.len());
+    Ok(())
+}
+
+/// The hwdb match for a device, in the same format as tools/generate-hwdb.py
+fn hwdb_modalias(device: &InspectionDevice) -> String {
+    let maybe_glob = |v: &str| {
+        let v = u32::from_str_radix(hex_without_prefix(v), 16).unwrap();
+        if v == 0 {
+            String::from("*")
+        } else {
+            format!("{v:04X}")
+        }
+    };
+    format!(
+        "hid:b{}g{}v0000{}p0000{}",
+        maybe_glob(&device.bus),
+        maybe_glob(&device.group),
+        maybe_glob(&device.vid),
+        maybe_glob(&device.pid)
+    )
+}
+
+/// Write the local hwdb file for all objects that were installed with --hwdb.
+///
+/// The properties point to the absolute path of the installed object, the
+/// udev-hid-bpf udev rule then loads them like any system-wide object.
+fn write_hwdb(hwdbfile: &mut dyn Write, objects: &[manifest::InstalledObject]) -> Result<()> {
+    let mut entries: Vec<(&str, &std::path::Path)> = objects
+        .iter()
+        .filter(|o| o.hwdb)
+        .filter_map(|o| {
+            o.files
+                .iter()
+                .find(|f| f.path.file_name() == Some(std::ffi::OsStr::new(&o.name)))
+                .map(|f| (o, f.path.as_path()))
+        })
+        .flat_map(|(o, path)| o.devices.iter().map(move |d| (d.as_str(), path)))
+        .collect();
+    entries.sort();
+
+    writeln!(
+        hwdbfile,
+        "# This file was generated by udev-hid-bpf install and will be overwritten. Do not edit"
+    )?;
+    writeln!(hwdbfile)?;
+    for (idx, (modalias, path)) in entries.iter().enumerate() {
+        writeln!(hwdbfile, "hid-bpf:{modalias}")?;
+        writeln!(hwdbfile, "  HID_BPF_L_{idx:03}={}", path.display())?;
+        writeln!(hwdbfile, "  .HID_BPF=1")?;
+        writeln!(hwdbfile)?;
+    }
+
+    Ok(())
+}
+
+/// Options for the install command, see Commands::Install
+struct InstallOptions {
     prefix: Option<PathBuf>,
+    root: Option<PathBuf>,
+    bpfdir: PathBuf,
+    udevdir: PathBuf,
     force: bool,
     install_exe: bool,
+    verify: bool,
+    hwdb: bool,
+    sign_key: Option<PathBuf>,
     dry_run: bool,
-) -> Result<()> {
+}
+
+/// Return the given path inside the root directory, if any
+fn rooted(root: &Option<PathBuf>, path: &std::path::Path) -> PathBuf {
+    match root {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+        None => PathBuf::from(path),
+    }
+}
+
+fn cmd_install(paths: &[PathBuf], opts: InstallOptions) -> Result<()> {
+    let InstallOptions {
+        prefix,
+        root,
+        bpfdir,
+        udevdir,
+        force,
+        install_exe,
+        verify,
+        hwdb,
+        sign_key,
+        dry_run,
+    } = opts;
+
     if dry_run {
         println!("This is a dry run, nothing will be created or installed");
     }
 
-    if !path.to_str().unwrap().ends_with(".bpf.o") {
-        bail!("Expected a bpf.o file as argument, not {path:?}");
+    // All paths are written into the rules and the manifest as they are on
+    // the target system, so they must not be relative
+    for dir in [&bpfdir, &udevdir].into_iter().chain(prefix.iter()) {
+        ensure!(dir.is_absolute(), "{dir:?} is not an absolute path");
     }
 
-    let idata = inspect(path)?;
-    if idata.devices.is_empty() {
-        bail!("{path:?} has no HID_DEVICE entries and must be manually attached");
+    let objfiles = objfiles_to_install(paths)?;
+    let sign_key = sign_key
+        .as_deref()
+        .map(signature::load_signing_key)
+        .transpose()?;
+
+    if verify {
+        let paths: Vec<PathBuf> = objfiles.iter().map(|(p, _)| p.clone()).collect();
+        verify_objfiles(&paths, &[]).context("Verification failed, nothing was installed")?;
     }
 
-    // udevdir is hardcoded for now, very few use-cases for the rule to be elsewhere
-    let udevdir = "/etc/udev/rules.d";
     // bindir is always $prefix/bin unless we use the fallback, then it's whatever meson said
     let bindir = prefix
         .as_ref()
         .map(|p| p.join("bin"))
         .unwrap_or(PathBuf::from(BINDIR));
 
-    // We install ourselves if requested
-    let exe = bindir.join("udev-hid-bpf");
-    if !exe.exists() {
-        if !install_exe {
-            bail!("{exe:?} does not exist. Install this project first or use --install-exe");
+    if hwdb {
+        // The hwdb entries are only used by our own udev rule
+        let have_rule = [
+            "/etc/udev/rules.d",
+            "/run/udev/rules.d",
+            "/usr/local/lib/udev/rules.d",
+            "/usr/lib/udev/rules.d",
+            "/lib/udev/rules.d",
+        ]
+        .iter()
+        .any(|d| rooted(&root, &PathBuf::from(d).join("81-hid-bpf.rules")).exists());
+        if !have_rule {
+            eprintln!("WARNING: 81-hid-bpf.rules is not installed, hwdb entries will not be used");
         }
+    } else {
+        // We install ourselves if requested
+        let exe = rooted(&root, &bindir.join("udev-hid-bpf"));
+        if !exe.exists() {
+            if !install_exe {
+                bail!("{exe:?} does not exist. Install this project first or use --install-exe");
+            }
 
-        println!("Installing myself as {exe:?}");
-        if !dry_run {
-            let myself = std::env::current_exe().unwrap();
-            std::fs::create_dir_all(exe.parent().unwrap())
-                .and_then(|_| std::fs::copy(myself, &exe))
-                .context("Failed to install myself as {exe:?}: {e}")?;
+            println!("Installing myself as {exe:?}");
+            if !dry_run {
+                let myself = std::env::current_exe().unwrap();
+                std::fs::create_dir_all(exe.parent().unwrap())
+                    .and_then(|_| std::fs::copy(myself, &exe))
+                    .context("Failed to install myself as {exe:?}: {e}")?;
+            }
         }
     }
 
-    let fwdir = PathBuf::from("/etc/udev-hid-bpf/");
+    let hwdbtarget = PathBuf::from(INSTALL_HWDB);
 
-    // We know it's .bpf.o suffixed
-    let filename: String = path.file_name().unwrap().to_string_lossy().to_string();
-    let stem = &filename.strip_suffix(".bpf.o").unwrap();
-    let target = fwdir.join(&filename);
-    let udevtarget = PathBuf::from(format!("{udevdir}/99-hid-bpf-{stem}.rules"));
+    // We know they're .bpf.o suffixed
+    let targets: Vec<(PathBuf, PathBuf)> = objfiles
+        .iter()
+        .map(|(_, idata)| {
+            let stem = idata.filename.strip_suffix(".bpf.o").unwrap();
+            (
+                bpfdir.join(&idata.filename),
+                udevdir.join(format!("99-hid-bpf-{stem}.rules")),
+            )
+        })
+        .collect();
+
+    // The manifest is saved after each object so that a failure part way
+    // through leaves no installed file untracked
+    let manifest_path = rooted(&root, &bpfdir.join(manifest::MANIFEST_FILE));
+    let mut manifest = manifest::Manifest::load(&manifest_path)?;
+    let save_manifest = |manifest: &manifest::Manifest| -> Result<()> {
+        if !dry_run {
+            std::fs::create_dir_all(manifest_path.parent().unwrap())?;
+            manifest.save(&manifest_path)?;
+        }
+        Ok(())
+    };
 
-    if !force {
-        for t in [&target, &udevtarget] {
+    for (target, rule) in &targets {
+        let target = rooted(&root, target);
+        let udevtarget = rooted(&root, rule);
+        if !force {
             ensure!(
-                !t.exists(),
-                format!("File {t:?} exists, remove first or use --force to overwrite")
+                !target.exists(),
+                format!("File {target:?} exists, remove first or use --force to overwrite")
+            );
+        }
+        if hwdb {
+            // A rule from an earlier install without --hwdb would load the
+            // program a second time. It is removed during the installation if
+            // we installed it, see below, anything else needs --force.
+            let tracked = manifest
+                .objects
+                .iter()
+                .any(|o| o.files.iter().any(|f| &f.path == rule));
+            if !force && !tracked {
+                ensure!(
+                    !udevtarget.exists(),
+                    format!(
+                        "{udevtarget:?} would load the program twice, remove it or use --force"
+                    )
+                );
+            }
+        } else if !force {
+            ensure!(
+                !udevtarget.exists(),
+                format!("File {udevtarget:?} exists, remove first or use --force to overwrite")
             );
         }
     }
 
-    println!("Installing {filename} as {target:?}");
-    if !dry_run {
-        std::fs::create_dir_all(target.parent().unwrap())
-            .and_then(|_| std::fs::copy(path, &target))
-            .context(format!("Failed to copy to {:?}", target))?;
+    for ((path, idata), (target, udevtarget)) in std::iter::zip(&objfiles, &targets) {
+        let filename = &idata.filename;
+        let data = objfile::read(path)?;
+
+        let dest = rooted(&root, target);
+        println!("Installing {filename} as {dest:?}");
+        if !dry_run {
+            std::fs::create_dir_all(dest.parent().unwrap())
+                .and_then(|_| std::fs::write(&dest, &data))
+                .context(format!("Failed to copy to {:?}", dest))?;
+        }
+
+        let mut files = vec![manifest::InstalledFile::new(target, &data)];
+
+        if let Some(key) = &sign_key {
+            let sig = signature::sign(key, &data);
+            let sigtarget = signature::signature_path(target);
+            let dest = rooted(&root, &sigtarget);
+            println!("Installing signature as {dest:?}");
+            if !dry_run {
+                std::fs::write(&dest, &sig)
+                    .context(format!("Failed to install signature {:?}", dest))?;
+            }
+            files.push(manifest::InstalledFile::new(&sigtarget, &sig));
+        }
+
+        if hwdb {
+            let dest = rooted(&root, udevtarget);
+            if dest.exists() {
+                println!("Removing udev rule {dest:?}, the hwdb now matches the program");
+                if !dry_run {
+                    std::fs::remove_file(&dest)
+                        .context(format!("Failed to remove udev rule {dest:?}"))?;
+                }
+            }
+        } else {
+            let mut rule: Vec<u8> = Vec::new();
+            write_udev_rule(
+                &mut rule,
+                &bindir,
+                target,
+                &idata.devices,
+                &idata.info.device_match,
+            )?;
+
+            let dest = rooted(&root, udevtarget);
+            println!("Installing udev rule as {:?}", dest);
+            if !dry_run {
+                std::fs::create_dir_all(dest.parent().unwrap())
+                    .and_then(|_| std::fs::write(&dest, &rule))
+                    .context(format!("Failed to install udev rule {:?}", dest))?;
+            } else {
+                println!("Printing udev rule instead of installing it:");
+                println!("---");
+                std::io::stdout().write_all(&rule)?;
+                println!("--");
+            }
+            files.push(manifest::InstalledFile::new(udevtarget, &rule));
+        }
+
+        manifest.insert(manifest::InstalledObject {
+            name: filename.clone(),
+            source: std::fs::canonicalize(path).unwrap_or(path.clone()),
+            date: manifest::now(),
+            files,
+            hwdb,
+            devices: idata.devices.iter().map(hwdb_modalias).collect(),
+        });
+        save_manifest(&manifest)?;
     }
 
-    println!("Installing udev rule as {:?}", udevtarget);
-    if !dry_run {
-        std::fs::create_dir_all(udevdir)?;
-        let mut rulefile = std::fs::File::create(&udevtarget)
-            .context(format!("Failed to install udev rule {:?}", udevtarget))?;
-        write_udev_rule(&mut rulefile, &bindir, &target, &idata.devices)?;
-    } else {
-        println!("Printing udev rule instead of installing it:");
-        println!("---");
-        write_udev_rule(&mut std::io::stdout(), &bindir, &target, &idata.devices)?;
-        println!("--");
+    // The hwdb file is regenerated from all objects in the manifest, so
+    // objects installed previously keep their entries
+    let update_hwdb = hwdb || manifest.hwdb.is_some();
+    if update_hwdb {
+        let mut hwdbdata: Vec<u8> = Vec::new();
+        write_hwdb(&mut hwdbdata, &manifest.objects)?;
+
+        let dest = rooted(&root, &hwdbtarget);
+        println!("Installing hwdb file as {dest:?}");
+        if !dry_run {
+            std::fs::create_dir_all(dest.parent().unwrap())
+                .and_then(|_| std::fs::write(&dest, &hwdbdata))
+                .context(format!("Failed to install hwdb file {:?}", dest))?;
+        } else {
+            println!("Printing hwdb file instead of installing it:");
+            println!("---");
+            std::io::stdout().write_all(&hwdbdata)?;
+            println!("--");
+        }
+        manifest.hwdb = Some(manifest::InstalledFile::new(&hwdbtarget, &hwdbdata));
+    }
+
+    println!("Recorded installed files in {manifest_path:?}");
+    save_manifest(&manifest)?;
+
+    // Staging into a root directory must not touch the host
+    if let Some(root) = root {
+        println!();
+        println!("Installation into {root:?} successful.");
+        if update_hwdb {
+            println!("Run `systemd-hwdb --root {root:?} update` to update the hwdb in that root.");
+        }
+        return Ok(());
     }
 
     if !dry_run {
+        if update_hwdb {
+            let updated = std::process::Command::new("systemd-hwdb")
+                .arg("update")
+                .status()
+                .or_else(|_| {
+                    std::process::Command::new("udevadm")
+                        .args(["hwdb", "--update"])
+                        .status()
+                });
+            if let Err(e) = updated {
+                eprintln!("WARNING: Failed to run `systemd-hwdb update`: {e:#}");
+            }
+        }
+
         if let Err(e) = std::process::Command::new("udevadm")
             .args(["control", "--reload"])
             .status()
@@ -578,9 +1296,388 @@ fn cmd_install(
     println!();
     println!("Installation successful. You can now plug in your device.");
     println!("To uninstall, run");
-    println!(" $ rm {target:?}");
-    println!(" $ rm {udevtarget:?}");
+    for (target, udevtarget) in &targets {
+        println!(" $ rm {target:?}");
+        if !hwdb {
+            println!(" $ rm {udevtarget:?}");
+        }
+    }
+    if update_hwdb {
+        println!(" $ rm {hwdbtarget:?}");
+        println!(" $ sudo systemd-hwdb update");
+    }
     println!(" $ sudo udevadm control --reload ");
+    println!("and remove the corresponding entries from {manifest_path:?}");
+    Ok(())
+}
+
+fn cmd_trace(devpath: &std::path::Path, annotate: bool) -> Result<()> {
+    let device = hidudev::HidUdev::from_syspath(devpath)?;
+    let programs = trace::DevicePrograms::from_sysname(&device.sysname())?;
+    for program in &programs.programs {
+        println!("Tracing program {}: {}", program.id, program.name);
+    }
+
+    let rdesc = if annotate {
+        let path = PathBuf::from(device.syspath()).join("report_descriptor");
+        let data = std::fs::read(&path).context(format!("Failed to read {path:?}"))?;
+        Some(rdesc::ReportDescriptor::parse(&data).context("Failed to parse report descriptor")?)
+    } else {
+        None
+    };
+
+    let instance = trace::TraceInstance::new()?;
+    let path = instance.trace_pipe();
+    let mut pipe = std::fs::File::open(&path).context(format!("Failed to open {path:?}"))?;
+
+    let mut decoder = trace::EventDecoder::default();
+    let mut buf = vec![0u8; 4096];
+    let mut pending: Vec<u8> = Vec::new();
+    // The last bpf_trace_printk() line, printed once a frame of
+    // its stack trace shows it came from one of our programs
+    let mut last_line: Option<String> = None;
+    loop {
+        let n = std::io::Read::read(&mut pipe, &mut buf).context("Failed to read trace_pipe")?;
+        pending.extend_from_slice(&buf[..n]);
+
+        while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(address) = trace::stack_frame(&line) {
+                if programs.contains(address) {
+                    if let Some(line) = last_line.take() {
+                        print_trace_line(&line, &mut decoder, rdesc.as_ref());
+                    }
+                }
+            } else if trace::TraceLine::parse(&line).is_some() {
+                last_line = Some(line.into_owned());
+            }
+        }
+    }
+}
+
+fn print_trace_line(
+    line: &str,
+    decoder: &mut trace::EventDecoder,
+    rdesc: Option<&rdesc::ReportDescriptor>,
+) {
+    let Some(line) = trace::TraceLine::parse(line) else {
+        return;
+    };
+    match decoder.feed(line.message) {
+        trace::Decoded::Partial => {}
+        trace::Decoded::Other => println!("{}: {}", line.timestamp, line.message),
+        trace::Decoded::Report(report) => {
+            println!("{}: report: {}", line.timestamp, format_report(&report));
+            if let Some(rdesc) = rdesc {
+                let fields: Vec<String> = rdesc
+                    .annotate(&report)
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {value}"))
+                    .collect();
+                println!("{}:   {}", line.timestamp, fields.join(", "));
+            }
+        }
+    }
+}
+
+fn format_report(report: &[u8]) -> String {
+    let bytes: Vec<String> = report.iter().map(|b| format!("{b:02x}")).collect();
+    bytes.join(" ")
+}
+
+fn print_monitored_report(
+    rdesc: &rdesc::ReportDescriptor,
+    elapsed: std::time::Duration,
+    report: Option<&[u8]>,
+    raw: Option<&monitor::RawReport>,
+) {
+    let timestamp = format!("{:12.6}", elapsed.as_secs_f64());
+    let raw_fields = raw.map(|raw| rdesc.annotate(&raw.data));
+    if let Some(raw) = raw {
+        let dropped = if raw.dropped { " (dropped)" } else { "" };
+        println!("{timestamp}: raw: {}{dropped}", format_report(&raw.data));
+    }
+    match report {
+        Some(report) => {
+            println!("{timestamp}: bpf: {}", format_report(report));
+            let fields = monitor::format_fields(&rdesc.annotate(report), raw_fields.as_deref());
+            println!("{timestamp}:   {fields}");
+        }
+        None => {
+            if let Some(raw_fields) = raw_fields {
+                println!(
+                    "{timestamp}:   {}",
+                    monitor::format_fields(&raw_fields, None)
+                );
+            }
+        }
+    }
+}
+
+fn cmd_monitor(devpath: &std::path::Path, raw: bool) -> Result<()> {
+    let device = hidudev::HidUdev::from_syspath(devpath)?;
+    let syspath = PathBuf::from(device.syspath());
+
+    let path = syspath.join("report_descriptor");
+    let data = std::fs::read(&path).context(format!("Failed to read {path:?}"))?;
+    let rdesc =
+        rdesc::ReportDescriptor::parse(&data).context("Failed to parse report descriptor")?;
+
+    let node = monitor::hidraw_node(&syspath)?;
+    let mut hidraw = std::fs::File::open(&node).context(format!("Failed to open {node:?}"))?;
+    let raw_monitor = if raw {
+        Some(monitor::RawMonitor::new(device.id())?)
+    } else {
+        None
+    };
+    println!("Monitoring {node:?}, press Ctrl+C to stop");
+
+    let mut pollfds = vec![libc::pollfd {
+        fd: std::os::fd::AsRawFd::as_raw_fd(&hidraw),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    if let Some(raw_monitor) = &raw_monitor {
+        pollfds.push(libc::pollfd {
+            fd: raw_monitor.fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+    }
+
+    let start = std::time::Instant::now();
+    let mut buf = vec![0u8; 16384];
+    let mut reports: std::collections::VecDeque<(std::time::Duration, Vec<u8>)> =
+        std::collections::VecDeque::new();
+    let mut raw_reports: std::collections::VecDeque<monitor::RawReport> =
+        std::collections::VecDeque::new();
+    loop {
+        // The hidraw report may be readable before its raw report
+        // shows up in the ring buffer, wait a bit before giving up on it
+        let rc = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, 100) };
+        if rc < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e).context("Failed to poll");
+        }
+
+        if pollfds[0].revents & libc::POLLIN != 0 {
+            let n = std::io::Read::read(&mut hidraw, &mut buf)
+                .context(format!("Failed to read {node:?}"))?;
+            reports.push_back((start.elapsed(), buf[..n].to_vec()));
+        } else if pollfds[0].revents & (libc::POLLHUP | libc::POLLERR) != 0 {
+            println!("Device {node:?} was removed");
+            return Ok(());
+        }
+
+        let Some(raw_monitor) = &raw_monitor else {
+            for (elapsed, report) in reports.drain(..) {
+                print_monitored_report(&rdesc, elapsed, Some(&report), None);
+            }
+            continue;
+        };
+
+        raw_reports.extend(raw_monitor.reports()?);
+        while let Some(raw) = raw_reports.front() {
+            if raw.dropped {
+                print_monitored_report(&rdesc, start.elapsed(), None, Some(raw));
+            } else if let Some((elapsed, report)) = reports.pop_front() {
+                print_monitored_report(&rdesc, elapsed, Some(&report), Some(raw));
+            } else {
+                break;
+            }
+            raw_reports.pop_front();
+        }
+
+        // Reports injected by a HID-BPF program have no raw report
+        while let Some((elapsed, report)) = reports.front() {
+            if start.elapsed() - *elapsed < std::time::Duration::from_millis(100) {
+                break;
+            }
+            print_monitored_report(&rdesc, *elapsed, Some(report), None);
+            reports.pop_front();
+        }
+    }
+}
+
+fn cmd_request(
+    devpath: &std::path::Path,
+    request: request::Request,
+    data: &[u8],
+    report_type: request::ReportType,
+    size: usize,
+) -> Result<()> {
+    let device = hidudev::HidUdev::from_syspath(devpath)?;
+    let hid_request = request::HidRequest::new()?;
+    match request {
+        request::Request::GetReport => {
+            let report = hid_request.get_report(device.id(), report_type, data, size)?;
+            println!("{}", format_report(&report));
+        }
+        request::Request::SetReport => {
+            let n = hid_request.set_report(device.id(), report_type, data)?;
+            println!("Sent {n} bytes");
+        }
+        request::Request::OutputReport => {
+            let n = hid_request.output_report(device.id(), data)?;
+            println!("Sent {n} bytes");
+        }
+    }
+    Ok(())
+}
+
+fn cmd_firmware_id(devpath: &std::path::Path) -> Result<()> {
+    let device = hidudev::HidUdev::from_syspath(devpath)?;
+    for (key, value) in huion::firmware_properties(&device)? {
+        println!("{key}={value}");
+    }
+    Ok(())
+}
+
+fn cmd_status(devpaths: &[PathBuf]) -> Result<()> {
+    let sysnames: Vec<String> = if devpaths.is_empty() {
+        std::fs::read_dir("/sys/bus/hid/devices")?
+            .flatten()
+            .map(|entry| String::from(entry.file_name().to_string_lossy()))
+            .filter(|sysname| PathBuf::from(bpf::get_bpffs_path(sysname, "")).exists())
+            .collect()
+    } else {
+        devpaths
+            .iter()
+            .map(|devpath| Ok(hidudev::HidUdev::from_syspath(devpath)?.sysname()))
+            .collect::<Result<_>>()?
+    };
+
+    if sysnames.is_empty() {
+        println!("No BPF objects are attached to any device");
+    } else {
+        println!("Attach order inferred from the link ids, objects without a link last");
+    }
+
+    for sysname in sysnames {
+        println!("{sysname}:");
+        match trace::attach_chain(&sysname) {
+            Ok(chain) if !chain.is_empty() => {
+                for (idx, object) in chain.iter().enumerate() {
+                    println!(
+                        "  {}. {}: {}",
+                        idx + 1,
+                        object.name,
+                        object.programs.join(", ")
+                    );
+                }
+            }
+            _ => println!("  no BPF objects attached"),
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_list_installed(root: Option<PathBuf>, bpfdir: PathBuf) -> Result<()> {
+    let manifest_path = rooted(&root, &bpfdir.join(manifest::MANIFEST_FILE));
+    let manifest = manifest::Manifest::load(&manifest_path)?;
+
+    if manifest.objects.is_empty() {
+        println!("No BPF objects installed via udev-hid-bpf install");
+        return Ok(());
+    }
+
+    println!("BPF objects installed via udev-hid-bpf install:");
+    for object in &manifest.objects {
+        println!("  -  name:      \"{}\"", object.name);
+        println!("     source:    {:?}", object.source);
+        println!("     installed: {}", object.date);
+        if object.hwdb {
+            println!("     matched through the local hwdb");
+        }
+        println!("     files:");
+        for file in &object.files {
+            println!("       - {:?}", file.path);
+        }
+    }
+
+    for object in &manifest.objects {
+        for file in &object.files {
+            match file.drift(&rooted(&root, &file.path)) {
+                Ok(Some(drift)) => eprintln!(
+                    "WARNING: {:?} (installed for {}) was {drift} since installation",
+                    file.path, object.name
+                ),
+                Ok(None) => {}
+                Err(e) => eprintln!(
+                    "WARNING: Failed to read {:?} (installed for {}): {e}",
+                    file.path, object.name
+                ),
+            }
+        }
+    }
+
+    if let Some(hwdb) = &manifest.hwdb {
+        match hwdb.drift(&rooted(&root, &hwdb.path)) {
+            Ok(Some(drift)) => {
+                eprintln!("WARNING: {:?} was {drift} since installation", hwdb.path)
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("WARNING: Failed to read {:?}: {e}", hwdb.path),
+        }
+    }
+
+    Ok(())
+}
+
+/// Load each object into the kernel without attaching it and run its
+/// probe against all currently connected matching devices whose properties
+/// and report descriptor match the object's metadata.
+///
+/// Returns an error if any object fails to load, a failing probe is
+/// only reported since the device may simply not need this object.
+fn verify_objfiles(objfiles: &[PathBuf], properties: &[hidudev::HidUdevProperty]) -> Result<()> {
+    let names: Vec<String> = objfiles
+        .iter()
+        .map(|p| String::from(p.to_string_lossy()))
+        .collect();
+    let object_device_map = find_sysfs_devices(&names)?;
+
+    let mut failed: Vec<String> = Vec::new();
+    for (path, name) in std::iter::zip(objfiles, &names) {
+        let filename = path.file_name().unwrap().to_string_lossy();
+
+        if let Err(e) = bpf::HidBPF::load_test(path, None, properties) {
+            println!("{filename}: {e:#}");
+            failed.push(filename.into());
+            continue;
+        }
+        println!("{filename}: loaded successfully");
+
+        let devices = object_device_map.get(name).cloned().unwrap_or_default();
+        if devices.is_empty() {
+            println!("  no matching device connected, probe not run");
+        }
+        let info = modalias::ObjectInfo::read(path)?;
+        for syspath in devices {
+            let device = hidudev::HidUdev::from_syspath(&syspath)?;
+            if let Err(e) = info
+                .device_match
+                .check(&device.device_properties())
+                .and_then(|_| Ok(device.report_descriptor()?))
+                .and_then(|rdesc| info.rdesc.check(&rdesc))
+            {
+                println!("  {}: {e:#}, probe not run", device.sysname());
+                continue;
+            }
+            match bpf::HidBPF::load_test(path, Some(&device), properties) {
+                Ok(_) => println!("  {}: probe succeeded", device.sysname()),
+                Err(e) => println!("  {}: {e:#}", device.sysname()),
+            }
+        }
+    }
+
+    ensure!(failed.is_empty(), "Failed to load {}", failed.join(", "));
+
     Ok(())
 }
 
@@ -641,26 +1738,32 @@ fn find_sysfs_devices(objfiles: &Vec<String>) -> Result<HashMap<String, Vec<Path
         .map(|entry| entry.path())
         .collect::<Vec<PathBuf>>();
 
-    let inspection_data = objfiles
-        .iter()
-        .map(|objfile| inspect(&PathBuf::from(objfile)))
-        .collect::<Result<Vec<InspectionData>>>()?;
-
     let mut map: HashMap<String, Vec<PathBuf>> = HashMap::new();
-    for (objfile, idata) in std::iter::zip(objfiles, inspection_data) {
+    for objfile in objfiles {
         // filename is something like 0003:045E:07A5.0002 but we only care
         // about the BUS:VID:PID part of that name
-        let prefixes = idata
-            .devices
-            .iter()
-            .map(device_vid_pid_name)
-            .collect::<Vec<String>>();
+        let path = PathBuf::from(objfile);
+        let prefixes = match index::Index::lookup(&path) {
+            Some(entry) => entry.sysname_prefixes(),
+            None => match objfile::bundle_entry(&path) {
+                Some(entry) => entry
+                    .modaliases
+                    .iter()
+                    .filter_map(|m| index::sysname_prefix(m))
+                    .collect(),
+                None => inspect(&path)?
+                    .devices
+                    .iter()
+                    .map(device_vid_pid_name)
+                    .collect::<Vec<String>>(),
+            },
+        };
 
         for d in &devices {
             for prefix in &prefixes {
                 let fname = d.file_name().unwrap().to_string_lossy();
                 if fname.starts_with(prefix) {
-                    log::debug!("{}: found compatible device {d:?}", idata.filename);
+                    log::debug!("{objfile}: found compatible device {d:?}");
                     map.entry(objfile.clone())
                         .or_insert(Vec::new())
                         .push(d.clone());
@@ -672,7 +1775,22 @@ fn find_sysfs_devices(objfiles: &Vec<String>) -> Result<HashMap<String, Vec<Path
     Ok(map)
 }
 
-fn udev_hid_bpf() -> Result<()> {
+/// Set up the signature check of every bpf.o file loaded into the kernel,
+/// see signature::check(). Must be called by every command that loads files.
+fn init_signature_policy(policy: Option<signature::Policy>) -> Result<()> {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => signature::configured_policy()?,
+    };
+    signature::init(
+        policy,
+        &PathBuf::from(signature::KEYS_DIR),
+        &PathBuf::from(INSTALL_FWDIR).join(manifest::MANIFEST_FILE),
+    )
+}
+
+/// Returns the process exit code, see the report::EXIT_* constants
+fn udev_hid_bpf() -> Result<u8> {
     let cli = Cli::parse();
 
     libbpf_rs::set_print(Some((
@@ -689,27 +1807,47 @@ fn udev_hid_bpf() -> Result<()> {
         modules.push("libbpf");
     }
 
-    stderrlog::new()
-        .modules(modules)
-        .show_module_names(true)
-        .verbosity(if cli.verbose {
-            log::LevelFilter::Debug
-        } else {
-            log::LevelFilter::Warn
-        })
-        .init()
-        .unwrap();
+    let level = if cli.verbose {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Warn
+    };
+
+    let logger: Box<dyn log::Log> = if cli.journal || journal::should_log_to_journal() {
+        Box::new(
+            journal::JournalLogger::new(&modules, level)
+                .context("Failed to connect to the journal")?,
+        )
+    } else {
+        let mut logger = stderrlog::new();
+        logger
+            .modules(modules)
+            .show_module_names(true)
+            .verbosity(level);
+        Box::new(logger)
+    };
+    jobs::init_logger(logger, level).unwrap();
 
-    match cli.command {
+    if let Some(path) = cli.verifier_log {
+        bpf::set_verifier_log_file(&path)?;
+    }
+
+    let rc = match cli.command {
         Commands::Add {
             paths,
             bpfdir,
             replace,
             property,
+            json,
+            exit_codes,
+            jobs,
         } => {
+            let jobs = jobs.unwrap_or_else(jobs::default_jobs);
+            init_signature_policy(cli.signature_policy)?;
+
             let (devices, objfiles) = split_paths(paths)?;
 
-            if devices.is_empty() {
+            let report = if devices.is_empty() {
                 let object_device_map: HashMap<String, Vec<PathBuf>> =
                     find_sysfs_devices(&objfiles)?;
 
@@ -729,42 +1867,130 @@ fn udev_hid_bpf() -> Result<()> {
 
                 // HashMap doesn't have a defined order, for better UX
                 // better UX we load objects in the order given on the cmdline.
-                objfiles
-                    .into_iter()
-                    .filter(|objfile| object_device_map.contains_key(objfile))
-                    .map(|objfile| object_device_map.get_key_value(&objfile).unwrap())
-                    .map(|(objfile, devices)| {
-                        let objfiles = vec![String::from(objfile)];
-                        cmd_add(&devices, objfiles.as_slice(), bpfdir.clone(), &property)
-                    })
-                    .collect::<Result<(), anyhow::Error>>()
+                let mut report = report::AddReport::default();
+                for (objfile, devices) in objfiles
+                    .iter()
+                    .filter_map(|objfile| object_device_map.get_key_value(objfile))
+                {
+                    let objfiles = vec![String::from(objfile)];
+                    let r = cmd_add(
+                        devices,
+                        objfiles.as_slice(),
+                        bpfdir.clone(),
+                        &property,
+                        jobs,
+                    )?;
+                    report.devices.extend(r.devices);
+                }
+                report
             } else {
                 let devices = devices.iter().map(PathBuf::from).collect();
                 if replace {
                     cmd_remove(&devices)?;
                     std::thread::sleep(std::time::Duration::from_millis(500));
                 }
-                cmd_add(&devices, &objfiles, bpfdir, &property)
+                cmd_add(&devices, &objfiles, bpfdir, &property, jobs)?
+            };
+
+            if json {
+                let json =
+                    serde_json::to_string_pretty(&report).context("Failed to generate json")?;
+                println!("{json}");
+            }
+
+            if let Some(device) = report.devices.iter().find(|d| d.error.is_some()) {
+                bail!(
+                    "Failed to open device {:?}: {}",
+                    device.syspath,
+                    device.error.as_deref().unwrap_or_default()
+                );
+            }
+
+            if json || exit_codes {
+                return Ok(report.exit_code());
             }
+            Ok(())
+        }
+        Commands::Remove {
+            devpaths,
+            exit_codes,
+        } => {
+            let rc = cmd_remove(&devpaths)?;
+            if exit_codes {
+                return Ok(rc);
+            }
+            Ok(())
         }
-        Commands::Remove { devpaths } => cmd_remove(&devpaths),
         Commands::ListBpfPrograms { bpfdir } => cmd_list_bpf_programs(bpfdir),
+        Commands::Index { dirs } => match dirs.is_empty() {
+            true => cmd_index(
+                &default_bpf_dirs()
+                    .into_iter()
+                    .filter(|d| d.exists())
+                    .collect::<Vec<PathBuf>>(),
+            ),
+            false => cmd_index(&dirs),
+        },
+        Commands::Which { bpfdir, target } => cmd_which(&target, bpfdir),
         Commands::ListDevices {} => cmd_list_devices(),
         Commands::Inspect { paths } => cmd_inspect(&paths),
+        Commands::Bundle { output, paths } => cmd_bundle(&output, &paths),
         Commands::Install {
-            path,
+            paths,
             prefix,
+            root,
+            bpf_dir,
+            udev_rules_dir,
             force,
             install_exe,
+            verify,
+            hwdb,
+            sign_key,
             dry_run,
-        } => cmd_install(&path, prefix, force, install_exe, dry_run),
-    }
+        } => {
+            if verify {
+                init_signature_policy(cli.signature_policy)?;
+            }
+            cmd_install(
+                &paths,
+                InstallOptions {
+                    prefix,
+                    root,
+                    bpfdir: bpf_dir,
+                    udevdir: udev_rules_dir,
+                    force,
+                    install_exe,
+                    verify,
+                    hwdb,
+                    sign_key,
+                    dry_run,
+                },
+            )
+        }
+        Commands::Verify { paths, property } => {
+            init_signature_policy(cli.signature_policy)?;
+            verify_objfiles(&paths, &property)
+        }
+        Commands::Trace { devpath, annotate } => cmd_trace(&devpath, annotate),
+        Commands::Monitor { devpath, raw } => cmd_monitor(&devpath, raw),
+        Commands::Request {
+            devpath,
+            request,
+            data,
+            report_type,
+            size,
+        } => cmd_request(&devpath, request, &data, report_type, size),
+        Commands::FirmwareId { devpath } => cmd_firmware_id(&devpath),
+        Commands::ListInstalled { root, bpf_dir } => cmd_list_installed(root, bpf_dir),
+        Commands::Status { devpaths } => cmd_status(&devpaths),
+    };
+    rc.map(|_| report::EXIT_SUCCESS)
 }
 
 fn main() -> ExitCode {
     let rc = udev_hid_bpf();
     match rc {
-        Ok(_) => ExitCode::SUCCESS,
+        Ok(rc) => ExitCode::from(rc),
         Err(e) => {
             eprintln!("Error: {e:#}");
             ExitCode::FAILURE
@@ -798,6 +2024,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_hex_byte_parse() {
+        assert_eq!(hex_byte_parse("02"), Ok(0x02));
+        assert_eq!(hex_byte_parse("0xff"), Ok(0xff));
+        assert_eq!(hex_byte_parse("a"), Ok(0x0a));
+        assert!(hex_byte_parse("100").is_err());
+        assert!(hex_byte_parse("zz").is_err());
+    }
+
     macro_rules! vec_of_strings {
         ($($x:expr),*) => (vec![$($x.to_string()),*]);
     }
@@ -875,6 +2110,116 @@ mod tests {
         assert!(tuple_parse("foobar").is_err());
     }
 
+    #[test]
+    fn test_rooted() {
+        let path = PathBuf::from("/etc/udev-hid-bpf/0010-foo.bpf.o");
+        assert_eq!(rooted(&None, &path), path);
+        assert_eq!(
+            rooted(&Some(PathBuf::from("/tmp/image")), &path),
+            PathBuf::from("/tmp/image/etc/udev-hid-bpf/0010-foo.bpf.o")
+        );
+        assert_eq!(
+            rooted(&Some(PathBuf::from("image")), &path),
+            PathBuf::from("image/etc/udev-hid-bpf/0010-foo.bpf.o")
+        );
+    }
+
+    #[test]
+    fn test_write_hwdb() {
+        let device = |bus: &str, group: &str, vid: &str, pid: &str| InspectionDevice {
+            bus: bus.into(),
+            group: group.into(),
+            vid: vid.into(),
+            pid: pid.into(),
+        };
+        assert_eq!(
+            hwdb_modalias(&device("0x0003", "0x0000", "0x256C", "0x006D")),
+            "hid:b0003g*v0000256Cp0000006D"
+        );
+        assert_eq!(
+            hwdb_modalias(&device("0x0018", "0x0001", "0x0000", "0x0000")),
+            "hid:b0018g0001v0000*p0000*"
+        );
+
+        let object = |name: &str, hwdb: bool, devices: &[&str]| manifest::InstalledObject {
+            name: name.into(),
+            source: PathBuf::from(name),
+            date: String::new(),
+            files: vec![
+                manifest::InstalledFile::new(&PathBuf::from(INSTALL_FWDIR).join(name), b""),
+                manifest::InstalledFile::new(&PathBuf::from("/etc/udev/rules.d/foo.rules"), b""),
+            ],
+            hwdb,
+            devices: devices.iter().map(|&d| d.into()).collect(),
+        };
+        let objects = [
+            object("0010-foo.bpf.o", true, &["hid:b0003g*v0000256Cp0000006D"]),
+            object("0010-bar.bpf.o", false, &["hid:b0003g*v0000256Cp0000006E"]),
+            object(
+                "0020-baz.bpf.o",
+                true,
+                &[
+                    "hid:b0005g*v0000256Cp0000006F",
+                    "hid:b0003g*v0000256Cp0000006F",
+                ],
+            ),
+        ];
+
+        let mut hwdb: Vec<u8> = Vec::new();
+        write_hwdb(&mut hwdb, &objects).unwrap();
+        let hwdb = String::from_utf8(hwdb).unwrap();
+        let entries: Vec<&str> = hwdb.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            entries,
+            vec![
+                "",
+                "hid-bpf:hid:b0003g*v0000256Cp0000006D",
+                "  HID_BPF_L_000=/etc/udev-hid-bpf/0010-foo.bpf.o",
+                "  .HID_BPF=1",
+                "",
+                "hid-bpf:hid:b0003g*v0000256Cp0000006F",
+                "  HID_BPF_L_001=/etc/udev-hid-bpf/0020-baz.bpf.o",
+                "  .HID_BPF=1",
+                "",
+                "hid-bpf:hid:b0005g*v0000256Cp0000006F",
+                "  HID_BPF_L_002=/etc/udev-hid-bpf/0020-baz.bpf.o",
+                "  .HID_BPF=1",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_udev_rule_uniq() {
+        let devices = vec![InspectionDevice {
+            bus: String::from("0x0003"),
+            group: String::from("0x0000"),
+            vid: String::from("0x256C"),
+            pid: String::from("0x006D"),
+        }];
+        let rule = |uniq: &str| {
+            let device_match = modalias::DeviceMatch {
+                uniq: vec![String::from(uniq)],
+                ..Default::default()
+            };
+            let mut rule: Vec<u8> = Vec::new();
+            write_udev_rule(
+                &mut rule,
+                &PathBuf::from("/usr/local/bin"),
+                &PathBuf::from("/etc/udev-hid-bpf/0010-foo.bpf.o"),
+                &devices,
+                &device_match,
+            )
+            .unwrap();
+            String::from_utf8(rule).unwrap()
+        };
+
+        assert!(rule("ab:cd").contains(r#"ENV{HID_UNIQ}=="ab:cd""#));
+        for uniq in ["a\"b", "ab*", "a?", "[ab]", "a|b", "a\\b"] {
+            assert!(!rule(uniq).contains("HID_UNIQ"), "{uniq}");
+        }
+    }
+
     #[test]
     fn test_hex_without_prefix() {
         assert_eq!(hex_without_prefix("0x0"), "0");
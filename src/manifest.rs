@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// The manifest file name, relative to the directory the objects are installed in.
+pub static MANIFEST_FILE: &str = "manifest.json";
+
+/// The set of objects installed with `udev-hid-bpf install`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub objects: Vec<InstalledObject>,
+    /// The local hwdb file generated for all objects installed with --hwdb
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hwdb: Option<InstalledFile>,
+}
+
+/// One bpf.o file and all files that were installed for it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstalledObject {
+    /// The file name of the object, e.g. 0010-foo.bpf.o
+    pub name: String,
+    /// The path the object was installed from
+    pub source: PathBuf,
+    /// ISO 8601 date (UTC) of the installation
+    pub date: String,
+    pub files: Vec<InstalledFile>,
+    /// The object is matched through the local hwdb instead of its own udev rule
+    #[serde(default)]
+    pub hwdb: bool,
+    /// The modalias matches of this object, e.g. hid:b0003g*v0000256Cp0000006D
+    #[serde(default)]
+    pub devices: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstalledFile {
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+    Missing,
+    Modified,
+}
+
+impl std::fmt::Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::Missing => write!(f, "deleted"),
+            Drift::Modified => write!(f, "modified"),
+        }
+    }
+}
+
+pub fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl InstalledFile {
+    pub fn new(path: &Path, data: &[u8]) -> Self {
+        InstalledFile {
+            path: path.into(),
+            sha256: sha256(data),
+        }
+    }
+
+    /// Compare the file on disk against the checksum recorded at install time.
+    /// The file is read from `path`, which differs from the recorded path if
+    /// the file was installed into a root directory.
+    ///
+    /// Returns an error if the file exists but can't be read.
+    pub fn drift(&self, path: &Path) -> std::io::Result<Option<Drift>> {
+        match std::fs::read(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Some(Drift::Missing)),
+            Err(e) => Err(e),
+            Ok(data) if sha256(&data) != self.sha256 => Ok(Some(Drift::Modified)),
+            Ok(_) => Ok(None),
+        }
+    }
+}
+
+impl Manifest {
+    /// Load the manifest at the given path. A nonexisting manifest
+    /// is an empty manifest.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let data = std::fs::read(path).context(format!("Failed to read {path:?}"))?;
+        serde_json::from_slice(&data).context(format!("Failed to parse manifest {path:?}"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to generate json")?;
+        std::fs::write(path, json + "\n").context(format!("Failed to write {path:?}"))
+    }
+
+    /// Add the object to the manifest, replacing any previous object of the same name.
+    pub fn insert(&mut self, object: InstalledObject) {
+        self.objects.retain(|o| o.name != object.name);
+        self.objects.push(object);
+        self.objects.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+}
+
+/// Convert a UNIX timestamp to a ISO 8601 date in UTC,
+/// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn iso8601(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+pub fn now() -> String {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    iso8601(timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso8601(1720094400), "2024-07-04T12:00:00Z");
+    }
+
+    #[test]
+    fn test_drift() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("0010-foo.bpf.o");
+        std::fs::write(&path, b"foo").unwrap();
+
+        let file = InstalledFile::new(&path, b"foo");
+        assert_eq!(file.drift(&path).unwrap(), None);
+
+        std::fs::write(&path, b"bar").unwrap();
+        assert_eq!(file.drift(&path).unwrap(), Some(Drift::Modified));
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file.drift(&path).unwrap(), Some(Drift::Missing));
+
+        // a directory exists but can't be read as a file
+        assert!(file.drift(tmpdir.path()).is_err());
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join(MANIFEST_FILE);
+
+        let manifest = Manifest::load(&path).unwrap();
+        assert!(manifest.objects.is_empty());
+
+        let mut manifest = Manifest::default();
+        for (name, date) in [
+            ("0010-foo.bpf.o", "a"),
+            ("0010-bar.bpf.o", "b"),
+            ("0010-foo.bpf.o", "c"),
+        ] {
+            manifest.insert(InstalledObject {
+                name: name.into(),
+                source: PathBuf::from("/tmp").join(name),
+                date: date.into(),
+                files: vec![],
+                hwdb: false,
+                devices: vec![],
+            });
+        }
+        manifest.save(&path).unwrap();
+
+        let manifest = Manifest::load(&path).unwrap();
+        let names: Vec<(&str, &str)> = manifest
+            .objects
+            .iter()
+            .map(|o| (o.name.as_str(), o.date.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("0010-bar.bpf.o", "b"), ("0010-foo.bpf.o", "c")]
+        );
+    }
+}
@@ -1,213 +1,367 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! The HID_BPF_CONFIG metadata of a bpf.o file, see the hid-modalias
+//! crate for the modalias itself.
+//!
+//! Numeric metadata (HID_DEVICE, HID_BPF_MIN_KERNEL, HID_BPF_UPSTREAM) is
+//! encoded in the BTF type of the HID_BPF_CONFIG union, each entry is a struct
+//! whose "name" member tells which kind of entry it is. Text metadata
+//! (HID_BPF_DESCRIPTION, HID_BPF_UDEV_PROP, HID_BPF_CONFLICTS) is stored as
+//! string constants in the .hid_bpf_config section of the ELF file.
+
+use crate::manifest;
+use crate::objfile;
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use libbpf_rs::btf::types as BtfTypes;
 use libbpf_rs::ReferencesType;
-
-#[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, PartialEq, Hash, Eq)]
-pub enum Bus {
-    Any,
-    PCI,
-    ISAPnP,
-    USB,
-    HIL,
-    Bluetooth,
-    Virtual,
-    ISA,
-    I8042,
-    XtKbd,
-    Rs232,
-    GamePort,
-    ParPort,
-    Amiga,
-    ADB,
-    I2C,
-    Host,
-    GSC,
-    Atari,
-    SPI,
-    RMI,
-    CEC,
-    IntelIshtp,
-    AmdSfh,
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub use hid_modalias::{Bus, Group, Modalias};
+
+static CONFIG_SECTION: &str = ".hid_bpf_config";
+
+/// The value of the "name" member of each HID_BPF_CONFIG entry
+const ENTRY_HID_DEVICE: usize = 0;
+const ENTRY_MIN_KERNEL: usize = 1;
+const ENTRY_UPSTREAM: usize = 2;
+const ENTRY_RDESC_SIZE: usize = 3;
+const ENTRY_USB_INTERFACE: usize = 4;
+const ENTRY_ATTACH_PRIORITY: usize = 5;
+const ENTRY_STACKABLE: usize = 6;
+
+/// A kernel version as used by HID_BPF_MIN_KERNEL, e.g. 6.11
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
 }
 
-impl TryFrom<usize> for Bus {
-    type Error = &'static str;
-
-    fn try_from(sz: usize) -> Result<Self, Self::Error> {
-        match sz {
-            0x00 => Ok(Bus::Any),
-            0x01 => Ok(Bus::PCI),
-            0x02 => Ok(Bus::ISAPnP),
-            0x03 => Ok(Bus::USB),
-            0x04 => Ok(Bus::HIL),
-            0x05 => Ok(Bus::Bluetooth),
-            0x06 => Ok(Bus::Virtual),
-            0x10 => Ok(Bus::ISA),
-            0x11 => Ok(Bus::I8042),
-            0x12 => Ok(Bus::XtKbd),
-            0x13 => Ok(Bus::Rs232),
-            0x14 => Ok(Bus::GamePort),
-            0x15 => Ok(Bus::ParPort),
-            0x16 => Ok(Bus::Amiga),
-            0x17 => Ok(Bus::ADB),
-            0x18 => Ok(Bus::I2C),
-            0x19 => Ok(Bus::Host),
-            0x1A => Ok(Bus::GSC),
-            0x1B => Ok(Bus::Atari),
-            0x1C => Ok(Bus::SPI),
-            0x1D => Ok(Bus::RMI),
-            0x1E => Ok(Bus::CEC),
-            0x1F => Ok(Bus::IntelIshtp),
-            0x20 => Ok(Bus::AmdSfh),
-            _ => Err("Invalid bus type"),
+impl KernelVersion {
+    /// The version of the running kernel
+    pub fn running() -> Option<Self> {
+        let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+        if unsafe { libc::uname(&mut uts) } != 0 {
+            return None;
         }
+        let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+        release.to_str().ok()?.parse().ok()
     }
 }
 
-impl From<&Bus> for usize {
-    fn from(bus: &Bus) -> Self {
-        match bus {
-            Bus::Any => 0x00,
-            Bus::PCI => 0x01,
-            Bus::ISAPnP => 0x02,
-            Bus::USB => 0x03,
-            Bus::HIL => 0x04,
-            Bus::Bluetooth => 0x05,
-            Bus::Virtual => 0x06,
-            Bus::ISA => 0x10,
-            Bus::I8042 => 0x11,
-            Bus::XtKbd => 0x12,
-            Bus::Rs232 => 0x13,
-            Bus::GamePort => 0x14,
-            Bus::ParPort => 0x15,
-            Bus::Amiga => 0x16,
-            Bus::ADB => 0x17,
-            Bus::I2C => 0x18,
-            Bus::Host => 0x19,
-            Bus::GSC => 0x1A,
-            Bus::Atari => 0x1B,
-            Bus::SPI => 0x1C,
-            Bus::RMI => 0x1D,
-            Bus::CEC => 0x1E,
-            Bus::IntelIshtp => 0x1F,
-            Bus::AmdSfh => 0x20,
+impl std::str::FromStr for KernelVersion {
+    type Err = anyhow::Error;
+
+    /// Parses 6.11 but also a kernel release like 6.11.0-rc1-foo
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(|c: char| !c.is_ascii_digit());
+        match (parts.next(), parts.next()) {
+            (Some(major), Some(minor)) if !major.is_empty() && !minor.is_empty() => {
+                Ok(KernelVersion {
+                    major: major.parse()?,
+                    minor: minor.parse()?,
+                })
+            }
+            _ => bail!("Invalid kernel version '{s}'"),
         }
     }
 }
 
-impl From<Bus> for String {
-    fn from(bus: Bus) -> String {
-        match bus {
-            Bus::Any => String::from("*"),
-            _ => format!("{:04X}", bus),
-        }
+impl std::fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
     }
 }
 
-#[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, PartialEq, Hash, Eq)]
-pub enum Group {
-    Any,
-    Generic,
-    Multitouch,
-    SensorHub,
-    MultitouchWin8,
-    RMI,
-    Wacom,
-    LogitechDJ,
-    Steam,
-    Logitech27mhz,
-    Vivaldi,
+impl TryFrom<String> for KernelVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
 }
 
-impl TryFrom<usize> for Group {
-    type Error = &'static str;
-
-    fn try_from(sz: usize) -> Result<Self, Self::Error> {
-        match sz {
-            0x00 => Ok(Group::Any),
-            0x01 => Ok(Group::Generic),
-            0x02 => Ok(Group::Multitouch),
-            0x03 => Ok(Group::SensorHub),
-            0x04 => Ok(Group::MultitouchWin8),
-            0x0100 => Ok(Group::RMI),
-            0x0101 => Ok(Group::Wacom),
-            0x0102 => Ok(Group::LogitechDJ),
-            0x0103 => Ok(Group::Steam),
-            0x0104 => Ok(Group::Logitech27mhz),
-            0x0105 => Ok(Group::Vivaldi),
-            _ => Err("Invalid group type"),
-        }
+impl From<KernelVersion> for String {
+    fn from(v: KernelVersion) -> String {
+        v.to_string()
     }
 }
 
-impl From<&Group> for usize {
-    fn from(group: &Group) -> Self {
-        match group {
-            Group::Any => 0x00,
-            Group::Generic => 0x01,
-            Group::Multitouch => 0x02,
-            Group::SensorHub => 0x03,
-            Group::MultitouchWin8 => 0x04,
-            Group::RMI => 0x0100,
-            Group::Wacom => 0x0101,
-            Group::LogitechDJ => 0x0102,
-            Group::Steam => 0x0103,
-            Group::Logitech27mhz => 0x0104,
-            Group::Vivaldi => 0x0105,
+/// The upstream status of the fix in an object, see HID_BPF_UPSTREAM
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpstreamStatus {
+    /// The fix is not submitted upstream
+    None,
+    Submitted,
+    Merged,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Upstream {
+    pub status: UpstreamStatus,
+    /// The first kernel version with the fix if it is merged
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub kernel: Option<KernelVersion>,
+}
+
+impl std::fmt::Display for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.status, self.kernel) {
+            (UpstreamStatus::None, _) => write!(f, "not upstream"),
+            (UpstreamStatus::Submitted, _) => write!(f, "submitted upstream"),
+            (UpstreamStatus::Merged, Some(kernel)) => write!(f, "merged upstream in {kernel}"),
+            (UpstreamStatus::Merged, None) => write!(f, "merged upstream"),
         }
     }
 }
 
-impl From<Group> for String {
-    fn from(group: Group) -> String {
-        match group {
-            Group::Any => String::from("*"),
-            _ => format!("{:04X}", group),
+/// A UDEV_PROP_* global of an object, see HID_BPF_UDEV_PROP
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UdevProperty {
+    /// The name of the global, e.g. UDEV_PROP_HUION_FIRMWARE_ID
+    pub name: String,
+    pub doc: String,
+}
+
+/// A byte pattern at an offset of the report descriptor,
+/// see HID_BPF_RDESC_BYTES
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RdescBytes {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// The report descriptor matches of an object. A device's report descriptor
+/// must match all kinds of matches, an object without any matches all report
+/// descriptors.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RdescMatch {
+    /// Any of these sizes, see HID_BPF_RDESC_SIZE
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub sizes: Vec<usize>,
+    /// Any of these checksums, see HID_BPF_RDESC_SHA256
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub sha256: Vec<String>,
+    /// All of these byte patterns
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub bytes: Vec<RdescBytes>,
+}
+
+impl RdescMatch {
+    pub fn is_empty(&self) -> bool {
+        self.sizes.is_empty() && self.sha256.is_empty() && self.bytes.is_empty()
+    }
+
+    /// Check a report descriptor against the matches,
+    /// the error says why it does not match
+    pub fn check(&self, rdesc: &[u8]) -> Result<()> {
+        ensure!(
+            self.sizes.is_empty() || self.sizes.contains(&rdesc.len()),
+            "report descriptor size {} is not {}",
+            rdesc.len(),
+            self.sizes
+                .iter()
+                .map(|size| size.to_string())
+                .collect::<Vec<String>>()
+                .join(" or ")
+        );
+        ensure!(
+            self.sha256.is_empty() || self.sha256.contains(&manifest::sha256(rdesc)),
+            "report descriptor checksum {} does not match",
+            manifest::sha256(rdesc)
+        );
+        for pattern in &self.bytes {
+            let bytes = pattern
+                .offset
+                .checked_add(pattern.bytes.len())
+                .and_then(|end| rdesc.get(pattern.offset..end));
+            ensure!(
+                bytes == Some(pattern.bytes.as_slice()),
+                "report descriptor bytes at offset {} are not {}",
+                pattern.offset,
+                pattern
+                    .bytes
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            );
         }
+        Ok(())
     }
 }
 
-impl std::fmt::UpperHex for Bus {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let val: usize = self.into();
-        std::fmt::UpperHex::fmt(&val, f)
-    }
+/// The udev properties of a device checked by DeviceMatch
+#[derive(Debug, Clone, Default)]
+pub struct DeviceProperties {
+    /// HID_NAME
+    pub name: String,
+    /// HID_PHYS
+    pub phys: String,
+    /// HID_UNIQ
+    pub uniq: String,
+    /// The bInterfaceNumber of the parent USB interface
+    pub usb_interface: Option<u32>,
 }
 
-impl std::fmt::LowerHex for Bus {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let val: usize = self.into();
-        std::fmt::LowerHex::fmt(&val, f)
-    }
+/// The device matches of an object beyond its HID_DEVICE entries. A device
+/// must match all kinds of matches, for each kind any of the values matches.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DeviceMatch {
+    /// Regular expressions matching the whole HID_NAME, see HID_BPF_NAME
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub names: Vec<String>,
+    /// Regular expressions matching the whole HID_PHYS, see HID_BPF_PHYS
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub phys: Vec<String>,
+    /// See HID_BPF_UNIQ
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub uniq: Vec<String>,
+    /// See HID_BPF_USB_INTERFACE
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub usb_interfaces: Vec<u32>,
 }
 
-impl std::fmt::UpperHex for Group {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let val: usize = self.into();
-        std::fmt::UpperHex::fmt(&val, f)
+/// A regular expression that must match the whole value
+fn full_regex(pattern: &str) -> Result<Regex> {
+    Regex::new(&format!("^(?:{pattern})$"))
+        .context(format!("Invalid regular expression '{pattern}'"))
+}
+
+impl DeviceMatch {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+            && self.phys.is_empty()
+            && self.uniq.is_empty()
+            && self.usb_interfaces.is_empty()
+    }
+
+    /// Check a device against the matches, the error says why it does not match
+    pub fn check(&self, device: &DeviceProperties) -> Result<()> {
+        for (patterns, value, property) in [
+            (&self.names, &device.name, "HID_NAME"),
+            (&self.phys, &device.phys, "HID_PHYS"),
+        ] {
+            if patterns.is_empty() {
+                continue;
+            }
+            let mut matches = false;
+            for pattern in patterns {
+                matches |= full_regex(pattern)?.is_match(value);
+            }
+            ensure!(matches, "{property} '{value}' does not match {patterns:?}");
+        }
+        ensure!(
+            self.uniq.is_empty() || self.uniq.contains(&device.uniq),
+            "HID_UNIQ '{}' is not any of {:?}",
+            device.uniq,
+            self.uniq
+        );
+        if !self.usb_interfaces.is_empty() {
+            match device.usb_interface {
+                Some(interface) => ensure!(
+                    self.usb_interfaces.contains(&interface),
+                    "USB interface {interface} is not any of {:?}",
+                    self.usb_interfaces
+                ),
+                None => bail!("not a USB interface"),
+            }
+        }
+        Ok(())
     }
 }
 
-impl std::fmt::LowerHex for Group {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let val: usize = self.into();
-        std::fmt::LowerHex::fmt(&val, f)
+/// The metadata of an object other than its HID_DEVICE matches
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ObjectInfo {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub upstream: Option<Upstream>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub min_kernel: Option<KernelVersion>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub udev_properties: Vec<UdevProperty>,
+    /// The objects this object must not be loaded together with, as
+    /// file names without priority prefix and suffix, e.g. Huion__Dial-2
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub conflicts: Vec<String>,
+    #[serde(skip_serializing_if = "RdescMatch::is_empty", default)]
+    pub rdesc: RdescMatch,
+    #[serde(skip_serializing_if = "DeviceMatch::is_empty", default)]
+    pub device_match: DeviceMatch,
+    /// Objects with a lower priority are attached first and thus run
+    /// first, see HID_BPF_ATTACH_PRIORITY
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub attach_priority: Option<u32>,
+    /// The objects this object must be attached before, see HID_BPF_ATTACH_BEFORE
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub attach_before: Vec<String>,
+    /// The objects this object must be attached after, see HID_BPF_ATTACH_AFTER
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub attach_after: Vec<String>,
+    /// This object extends the objects with the same stem and a lower
+    /// priority prefix instead of replacing them, see HID_BPF_STACKABLE
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub stackable: bool,
+}
+
+/// Whether any of the object names (file names without priority prefix and
+/// suffix, e.g. Huion__Dial-2) is the object with the given stem, i.e. its
+/// lowercase file name without the priority prefix
+pub fn names_stem(names: &[String], stem: &str) -> bool {
+    names
+        .iter()
+        .any(|name| format!("{}{}", name.to_lowercase(), objfile::OBJFILE_SUFFIX) == stem)
+}
+
+impl ObjectInfo {
+    /// Read the metadata of the bpf.o file at path
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::from_data(path, &objfile::read(path)?)
+    }
+
+    /// The metadata of the bpf.o file at path given its decompressed data
+    pub fn from_data(path: &Path, data: &[u8]) -> Result<Self> {
+        let filename = objfile::filename(path);
+        let btf = libbpf_rs::btf::Btf::from_raw(&filename, data)
+            .context(format!("Failed to read BPF from {path:?}"))?;
+        btf.as_ref()
+            .and_then(|btf| Metadata::from_btf(btf, data))
+            .map(|metadata| metadata.info())
+            .transpose()
+            .context(format!("Invalid HID_BPF_CONFIG metadata in {path:?}"))
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Whether this object conflicts with the object with the given
+    /// stem, i.e. its lowercase file name without the priority prefix
+    pub fn conflicts_with(&self, stem: &str) -> bool {
+        names_stem(&self.conflicts, stem)
     }
 }
 
 pub struct Metadata<'m> {
     btf: &'m libbpf_rs::btf::Btf<'m>,
-    types: BtfTypes::Union<'m>,
+    types: Option<BtfTypes::Union<'m>>,
+    /// (variable name, data) of the constants in the section, an error
+    /// if the section could not be read
+    symbols: Result<Vec<(String, Vec<u8>)>>,
 }
 
 impl<'m> Metadata<'m> {
-    pub fn from_btf<'a>(btf: &'a libbpf_rs::btf::Btf<'m>) -> Option<Self>
+    /// The metadata of the object with the given BTF and ELF data,
+    /// None if the object has no .hid_bpf_config section
+    pub fn from_btf<'a>(btf: &'a libbpf_rs::btf::Btf<'m>, data: &[u8]) -> Option<Self>
     where
         'a: 'm,
     {
-        let datasec = btf.type_by_name::<libbpf_rs::btf::types::DataSec>(".hid_bpf_config")?;
+        let datasec = btf.type_by_name::<libbpf_rs::btf::types::DataSec>(CONFIG_SECTION)?;
 
+        let mut types = None;
         for var_sec_info in datasec.iter() {
             log::debug!(target:"HID-BPF metadata", "{:?}", var_sec_info);
 
@@ -218,167 +372,622 @@ impl<'m> Metadata<'m> {
             log::debug!(target:"HID-BPF metadata", "  -> {:?} / {:?}", var, var_type);
 
             if let Ok(hb_union) = BtfTypes::Union::try_from(var_type) {
-                return Some(Metadata {
-                    btf,
-                    types: hb_union,
-                });
+                types = Some(hb_union);
+                break;
             }
         }
 
-        None
+        let symbols = section_symbols(data, CONFIG_SECTION)
+            .context(format!("Failed to read the {CONFIG_SECTION} constants"));
+
+        Some(Metadata {
+            btf,
+            types,
+            symbols,
+        })
+    }
+
+    /// The entries of the HID_BPF_CONFIG union of the given kind
+    fn entries(&self, kind: usize) -> Result<Vec<BtfTypes::Struct<'m>>> {
+        let mut entries = Vec::new();
+        for member in self.types.iter().flat_map(|types| types.iter()) {
+            let entry = self
+                .btf
+                .type_by_id::<BtfTypes::Struct>(member.ty)
+                .context("HID_BPF_CONFIG entry is not a struct")?;
+            if array_sizes(self.btf, &entry)?
+                .iter()
+                .any(|(name, value)| name == "name" && *value == kind)
+            {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
     }
 
-    pub fn modaliases(&self) -> impl Iterator<Item = Modalias> + '_ {
+    /// All HID_DEVICE entries of the .hid_bpf_config section, an error
+    /// if any entry is invalid
+    pub fn modaliases(&self) -> Result<Vec<Modalias>> {
         /* parse the HID_BPF config section */
-        self.types
+        self.entries(ENTRY_HID_DEVICE)?
             .iter()
-            .filter_map(|e| Modalias::from_btf_type_id(self.btf, e))
+            .map(|e| modalias_from_btf_struct(self.btf, e))
+            .collect()
     }
-}
 
-#[derive(Debug, Hash, Eq, PartialEq)]
-pub struct Modalias {
-    pub bus: Bus,
-    pub group: Group,
-    pub vid: u32,
-    pub pid: u32,
-}
+    /// The numeric members of the only entry of the given kind,
+    /// None if there is no such entry
+    fn single_entry(&self, kind: usize, macro_name: &str) -> Result<Option<Vec<(String, u32)>>> {
+        let entries = self.entries(kind)?;
+        ensure!(entries.len() <= 1, "{macro_name} used more than once");
+        entries
+            .first()
+            .map(|entry| {
+                array_sizes(self.btf, entry)?
+                    .into_iter()
+                    .map(|(name, value)| {
+                        u32::try_from(value)
+                            .map(|value| (name, value))
+                            .map_err(|_| anyhow!("Invalid {macro_name} value {value:#X}"))
+                    })
+                    .collect()
+            })
+            .transpose()
+    }
 
-impl Modalias {
-    fn new() -> Modalias {
-        Modalias {
-            bus: Bus::Any,
-            group: Group::Any,
-            vid: 0,
-            pid: 0,
-        }
+    /// The HID_BPF_MIN_KERNEL entry
+    pub fn min_kernel(&self) -> Result<Option<KernelVersion>> {
+        Ok(self
+            .single_entry(ENTRY_MIN_KERNEL, "HID_BPF_MIN_KERNEL")?
+            .map(|members| KernelVersion {
+                major: member_value(&members, "major"),
+                minor: member_value(&members, "minor"),
+            }))
     }
 
-    fn from_btf_type_id(
-        btf: &libbpf_rs::btf::Btf,
-        union_member: BtfTypes::UnionMember,
-    ) -> Option<Modalias> {
-        let device_descr = btf.type_by_id::<BtfTypes::Struct>(union_member.ty)?;
-        let mut modalias = Modalias::new();
+    /// The HID_BPF_ATTACH_PRIORITY entry
+    pub fn attach_priority(&self) -> Result<Option<u32>> {
+        Ok(self
+            .single_entry(ENTRY_ATTACH_PRIORITY, "HID_BPF_ATTACH_PRIORITY")?
+            .map(|members| member_value(&members, "priority")))
+    }
 
-        for member in device_descr.iter() {
-            let member_name = String::from(member.name.unwrap().to_str().unwrap());
-            log::debug!(target:"HID-BPF metadata", "    -> {:?}", member);
-            if let Some(Ok(array)) = btf
-                .type_by_id::<BtfTypes::Ptr>(member.ty)
-                .map(|pointer| BtfTypes::Array::try_from(pointer.referenced_type()))
-            {
-                match member_name.as_str() {
-                    "bus" => modalias.bus = Bus::try_from(array.capacity()).unwrap(),
-                    "group" => modalias.group = Group::try_from(array.capacity()).unwrap(),
-                    "vid" => modalias.vid = u32::try_from(array.capacity()).unwrap(),
-                    "pid" => modalias.pid = u32::try_from(array.capacity()).unwrap(),
-                    _ => (),
-                }
-                log::debug!(target:"HID-BPF metadata", "      -> {:?}: {:#06X}", member_name, array.capacity());
-            }
-        }
-        Some(modalias)
+    /// Whether the object has a HID_BPF_STACKABLE entry
+    pub fn stackable(&self) -> Result<bool> {
+        Ok(self
+            .single_entry(ENTRY_STACKABLE, "HID_BPF_STACKABLE")?
+            .is_some())
+    }
+
+    /// The HID_BPF_UPSTREAM entry
+    pub fn upstream(&self) -> Result<Option<Upstream>> {
+        let Some(members) = self.single_entry(ENTRY_UPSTREAM, "HID_BPF_UPSTREAM")? else {
+            return Ok(None);
+        };
+        let status = match member_value(&members, "status") {
+            1 => UpstreamStatus::None,
+            2 => UpstreamStatus::Submitted,
+            3 => UpstreamStatus::Merged,
+            value => bail!("Invalid HID_BPF_UPSTREAM status {value}"),
+        };
+        let kernel = match (member_value(&members, "major"), status) {
+            (0, _) | (_, UpstreamStatus::None) => None,
+            (major, _) => Some(KernelVersion {
+                major,
+                minor: member_value(&members, "minor"),
+            }),
+        };
+        Ok(Some(Upstream { status, kernel }))
+    }
+
+    /// The (name without prefix, data) of the constants whose name
+    /// starts with the given prefix
+    fn constants<'s>(&'s self, prefix: &'s str) -> impl Iterator<Item = (&'s str, &'s [u8])> {
+        let symbols = self.symbols.iter().flatten();
+        symbols.filter_map(move |(name, data)| {
+            name.strip_prefix(prefix)
+                .map(|suffix| (suffix, data.as_slice()))
+        })
     }
-}
 
-impl std::str::FromStr for Modalias {
-    type Err = std::io::Error;
+    /// The string constants whose name starts with the given prefix
+    fn strings<'s>(&'s self, prefix: &'s str) -> impl Iterator<Item = (&'s str, String)> {
+        self.constants(prefix).map(|(suffix, data)| {
+            let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+            (suffix, String::from_utf8_lossy(&data[..end]).into_owned())
+        })
+    }
 
-    fn from_str(modalias: &str) -> Result<Self, Self::Err> {
-        /* strip out the "hid:" prefix from the modalias */
-        let modalias = modalias.trim_start_matches("hid:");
+    /// The HID_BPF_DESCRIPTION string
+    pub fn description(&self) -> Option<String> {
+        self.strings("_hid_bpf_description")
+            .map(|(_, value)| value)
+            .next()
+    }
 
-        if modalias.len() != 28 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Invalid modalias '{}'", modalias),
-            ));
-        }
+    /// The HID_BPF_UDEV_PROP strings
+    pub fn udev_properties(&self) -> Vec<UdevProperty> {
+        self.strings("_hid_bpf_udev_prop_")
+            .map(|(prop, doc)| UdevProperty {
+                name: format!("UDEV_PROP_{prop}"),
+                doc,
+            })
+            .collect()
+    }
+
+    /// The HID_BPF_CONFLICTS strings
+    pub fn conflicts(&self) -> Vec<String> {
+        self.strings("_hid_bpf_conflicts")
+            .map(|(_, value)| value)
+            .collect()
+    }
 
-        let econvert = |_| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Invalid modalias '{}'", modalias),
-            )
+    /// The HID_BPF_RDESC_SIZE, HID_BPF_RDESC_SHA256 and
+    /// HID_BPF_RDESC_BYTES entries
+    pub fn rdesc_match(&self) -> Result<RdescMatch> {
+        let sizes = self
+            .entries(ENTRY_RDESC_SIZE)?
+            .iter()
+            .map(|entry| {
+                array_sizes(self.btf, entry)?
+                    .into_iter()
+                    .find(|(name, _)| name == "size")
+                    .map(|(_, size)| size)
+                    .context("HID_BPF_RDESC_SIZE without size")
+            })
+            .collect::<Result<Vec<usize>>>()?;
+
+        let sha256 = self
+            .strings("_hid_bpf_rdesc_sha256_")
+            .map(|(_, hash)| {
+                ensure!(
+                    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()),
+                    "Invalid HID_BPF_RDESC_SHA256 '{hash}'"
+                );
+                Ok(hash.to_lowercase())
+            })
+            .collect::<Result<Vec<String>>>()?;
+
+        // The variable name is _hid_bpf_rdesc_bytes_<offset>__<line>
+        let bytes = self
+            .constants("_hid_bpf_rdesc_bytes_")
+            .map(|(suffix, bytes)| {
+                let offset = suffix.split_once("__").map(|(offset, _)| offset);
+                let offset = match offset {
+                    Some(hex) if hex.starts_with("0x") || hex.starts_with("0X") => {
+                        usize::from_str_radix(&hex[2..], 16).ok()
+                    }
+                    Some(dec) => dec.parse().ok(),
+                    None => None,
+                }
+                .context(format!("Invalid HID_BPF_RDESC_BYTES offset in {suffix}"))?;
+                Ok(RdescBytes {
+                    offset,
+                    bytes: bytes.to_vec(),
+                })
+            })
+            .collect::<Result<Vec<RdescBytes>>>()?;
+
+        Ok(RdescMatch {
+            sizes,
+            sha256,
+            bytes,
+        })
+    }
+
+    /// The HID_BPF_NAME, HID_BPF_PHYS, HID_BPF_UNIQ and
+    /// HID_BPF_USB_INTERFACE entries
+    pub fn device_match(&self) -> Result<DeviceMatch> {
+        let usb_interfaces = self
+            .entries(ENTRY_USB_INTERFACE)?
+            .iter()
+            .map(|entry| {
+                array_sizes(self.btf, entry)?
+                    .into_iter()
+                    .find(|(name, _)| name == "interface")
+                    .and_then(|(_, interface)| u32::try_from(interface).ok())
+                    .context("Invalid HID_BPF_USB_INTERFACE")
+            })
+            .collect::<Result<Vec<u32>>>()?;
+
+        let device_match = DeviceMatch {
+            names: self
+                .strings("_hid_bpf_match_name_")
+                .map(|(_, v)| v)
+                .collect(),
+            phys: self
+                .strings("_hid_bpf_match_phys_")
+                .map(|(_, v)| v)
+                .collect(),
+            uniq: self
+                .strings("_hid_bpf_match_uniq_")
+                .map(|(_, v)| v)
+                .collect(),
+            usb_interfaces,
         };
+        for pattern in device_match.names.iter().chain(&device_match.phys) {
+            full_regex(pattern)?;
+        }
+        Ok(device_match)
+    }
 
-        let bus =
-            Bus::try_from(usize::from_str_radix(&modalias[1..5], 16).map_err(econvert)?).unwrap();
-        let group = Group::try_from(usize::from_str_radix(&modalias[6..10], 16).map_err(econvert)?)
-            .unwrap();
-        let vid = u32::from_str_radix(&modalias[11..19], 16).map_err(econvert)?;
-        let pid = u32::from_str_radix(&modalias[20..28], 16).map_err(econvert)?;
-
-        Ok(Self {
-            bus,
-            group,
-            vid,
-            pid,
+    /// All metadata other than the HID_DEVICE entries, an error if any
+    /// entry or the constants of the section are invalid
+    pub fn info(&self) -> Result<ObjectInfo> {
+        if let Err(e) = &self.symbols {
+            bail!("{e:#}");
+        }
+        Ok(ObjectInfo {
+            description: self.description(),
+            upstream: self.upstream()?,
+            min_kernel: self.min_kernel()?,
+            udev_properties: self.udev_properties(),
+            conflicts: self.conflicts(),
+            rdesc: self.rdesc_match()?,
+            device_match: self.device_match()?,
+            attach_priority: self.attach_priority()?,
+            attach_before: self
+                .strings("_hid_bpf_attach_before")
+                .map(|(_, v)| v)
+                .collect(),
+            attach_after: self
+                .strings("_hid_bpf_attach_after")
+                .map(|(_, v)| v)
+                .collect(),
+            stackable: self.stackable()?,
         })
     }
 }
 
-impl From<Modalias> for String {
-    fn from(modalias: Modalias) -> String {
-        let vid = match modalias.vid {
-            0 => String::from("*"),
-            _ => format!("{:08X}", modalias.vid),
-        };
-        let pid = match modalias.pid {
-            0 => String::from("*"),
-            _ => format!("{:08X}", modalias.pid),
-        };
+fn member_value(members: &[(String, u32)], name: &str) -> u32 {
+    members
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| *value)
+        .unwrap_or_default()
+}
+
+/// The (name, array size) of all __uint() members of a HID_BPF_CONFIG entry
+fn array_sizes(
+    btf: &libbpf_rs::btf::Btf,
+    entry: &BtfTypes::Struct,
+) -> Result<Vec<(String, usize)>> {
+    let mut sizes = Vec::new();
+    for member in entry.iter() {
+        let member_name = member
+            .name
+            .and_then(|name| name.to_str().ok())
+            .context("HID_BPF_CONFIG entry has an unnamed member")?;
+        log::debug!(target:"HID-BPF metadata", "    -> {:?}", member);
+        if let Some(Ok(array)) = btf
+            .type_by_id::<BtfTypes::Ptr>(member.ty)
+            .map(|pointer| BtfTypes::Array::try_from(pointer.referenced_type()))
+        {
+            let value = array.capacity();
+            log::debug!(target:"HID-BPF metadata", "      -> {:?}: {:#06X}", member_name, value);
+            sizes.push((String::from(member_name), value));
+        }
+    }
+    Ok(sizes)
+}
+
+/// A HID_DEVICE entry of the .hid_bpf_config section
+fn modalias_from_btf_struct(
+    btf: &libbpf_rs::btf::Btf,
+    device_descr: &BtfTypes::Struct,
+) -> Result<Modalias> {
+    let mut modalias = Modalias::default();
+
+    for (member_name, value) in array_sizes(btf, device_descr)? {
+        match member_name.as_str() {
+            "bus" => modalias.bus = Bus::try_from(value)?,
+            "group" => modalias.group = Group::try_from(value)?,
+            "vid" => {
+                modalias.vid =
+                    u32::try_from(value).map_err(|_| anyhow!("Invalid vendor ID {value:#X}"))?
+            }
+            "pid" => {
+                modalias.pid =
+                    u32::try_from(value).map_err(|_| anyhow!("Invalid product ID {value:#X}"))?
+            }
+            _ => (),
+        }
+    }
+    Ok(modalias)
+}
+
+/// The (symbol name, data) of all _hid_bpf_ constants in the given section
+/// of a 64-bit ELF file. BTF has the names of these variables but their
+/// offsets are only filled in by libbpf when loading the object, so we use
+/// the ELF symbol table instead.
+fn section_symbols(data: &[u8], section: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    ensure!(
+        data.len() >= 64 && data.starts_with(b"\x7fELF"),
+        "Not an ELF file"
+    );
+    ensure!(data[4] == 2, "Not a 64-bit ELF file");
+    let big_endian = data[5] == 2;
+
+    let bytes = |offset: usize, len: usize| -> Result<&[u8]> {
+        offset
+            .checked_add(len)
+            .and_then(|end| data.get(offset..end))
+            .context("Truncated ELF file")
+    };
+    let uint = |offset: usize, len: usize| -> Result<usize> {
+        let bytes = bytes(offset, len)?;
+        let mut value = 0usize;
+        for i in 0..len {
+            let byte = if big_endian {
+                bytes[i]
+            } else {
+                bytes[len - 1 - i]
+            };
+            value = (value << 8) | byte as usize;
+        }
+        Ok(value)
+    };
+    // All offsets and sizes come from the file, don't let them overflow
+    let add =
+        |a: usize, b: usize| -> Result<usize> { a.checked_add(b).context("Invalid ELF file") };
+    let cstr = |offset: usize| -> Result<String> {
+        let rest = data.get(offset..).context("Truncated ELF file")?;
+        let end = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    };
+
+    let shoff = uint(0x28, 8)?;
+    let shentsize = uint(0x3A, 2)?;
+    let shnum = uint(0x3C, 2)?;
+    let shstrndx = uint(0x3E, 2)?;
+    // (sh_name, sh_type, sh_offset, sh_size, sh_link) of each section
+    let headers = (0..shnum)
+        .map(|idx| {
+            let header = add(shoff, idx * shentsize)?;
+            Ok((
+                uint(header, 4)?,
+                uint(add(header, 4)?, 4)?,
+                uint(add(header, 24)?, 8)?,
+                uint(add(header, 32)?, 8)?,
+                uint(add(header, 40)?, 4)?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (_, _, shstrtab, _, _) = headers.get(shstrndx).context("Invalid ELF file")?;
 
-        format!(
-            "b{}g{}v{}p{}",
-            String::from(modalias.bus),
-            String::from(modalias.group),
-            vid,
-            pid
-        )
+    let mut config = None;
+    for (idx, (name, ..)) in headers.iter().enumerate() {
+        if cstr(add(*shstrtab, *name)?)? == section {
+            config = Some(idx);
+        }
     }
+    let Some(config) = config else {
+        return Ok(Vec::new());
+    };
+    let (_, _, config_offset, config_size, _) = headers[config];
+
+    const SHT_SYMTAB: usize = 2;
+    let mut symbols = Vec::new();
+    for (_, _, offset, size, link) in headers.iter().filter(|h| h.1 == SHT_SYMTAB) {
+        let (_, _, strtab, _, _) = headers.get(*link).context("Invalid ELF symbol table")?;
+        for sym in (*offset..add(*offset, *size)?).step_by(24) {
+            if uint(add(sym, 6)?, 2)? != config {
+                continue;
+            }
+            let (value, len) = (uint(add(sym, 8)?, 8)?, uint(add(sym, 16)?, 8)?);
+            ensure!(add(value, len)? <= config_size, "Invalid ELF symbol");
+            let name = cstr(add(*strtab, uint(sym, 4)?)?)?;
+            if !name.starts_with("_hid_bpf_") || len == 0 {
+                continue;
+            }
+            symbols.push((name, bytes(add(config_offset, value)?, len)?.to_vec()));
+        }
+    }
+    Ok(symbols)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::modalias::{Bus, Group};
-    use std::str::FromStr;
 
     #[test]
-    fn test_modalias() {
-        let modalias = "b0003g0001v000004D9p0000A09F";
-        let m = Modalias::from_str(modalias);
-        assert!(m.is_ok());
-        let m = m.unwrap();
-        assert!(m.bus == Bus::USB);
-        assert!(m.group == Group::Generic);
-        assert!(m.vid == 0x04d9);
-        assert!(m.pid == 0xa09f);
-
-        // parsing doesn't care about uppercase hex
-        let m = Modalias::from_str(modalias.to_lowercase().as_str());
-        assert!(m.is_ok());
-        let m = m.unwrap();
-        assert!(m.bus == Bus::USB);
-        assert!(m.group == Group::Generic);
-        assert!(m.vid == 0x04d9);
-        assert!(m.pid == 0xa09f);
-
-        // 4-digit vid
-        let modalias = "b0003g0001v04D9p0000A09F";
-        let m = Modalias::from_str(modalias.to_lowercase().as_str());
-        assert!(m.is_err());
-
-        // 4-digit pid
-        let modalias = "b0003g0001v000004D9pA09F";
-        let m = Modalias::from_str(modalias.to_lowercase().as_str());
-        assert!(m.is_err());
-
-        // invalid char
-        let modalias = "b0003g0001v0000g4D9pA09F";
-        let m = Modalias::from_str(modalias.to_lowercase().as_str());
-        assert!(m.is_err());
+    fn test_kernel_version() {
+        let v: KernelVersion = "6.11".parse().unwrap();
+        assert_eq!(
+            v,
+            KernelVersion {
+                major: 6,
+                minor: 11
+            }
+        );
+        let v: KernelVersion = "6.12.0-rc1-00042-g1234".parse().unwrap();
+        assert_eq!(
+            v,
+            KernelVersion {
+                major: 6,
+                minor: 12
+            }
+        );
+        assert!("6".parse::<KernelVersion>().is_err());
+        assert!("foo".parse::<KernelVersion>().is_err());
+
+        assert!("6.9".parse::<KernelVersion>().unwrap() < "6.11".parse().unwrap());
+        assert!("5.19".parse::<KernelVersion>().unwrap() < "6.1".parse().unwrap());
+        assert_eq!(v.to_string(), "6.12");
+    }
+
+    #[test]
+    fn test_rdesc_match() {
+        let rdesc = [0x05, 0x01, 0x09, 0x04, 0xa1, 0x01, 0xc0];
+
+        let mut m = RdescMatch::default();
+        assert!(m.is_empty());
+        assert!(m.check(&rdesc).is_ok());
+
+        m.sizes = vec![5, 7];
+        assert!(m.check(&rdesc).is_ok());
+        assert!(m.check(&rdesc[..6]).is_err());
+
+        m.bytes = vec![RdescBytes {
+            offset: 2,
+            bytes: vec![0x09, 0x04],
+        }];
+        assert!(m.check(&rdesc).is_ok());
+        m.bytes.push(RdescBytes {
+            offset: 6,
+            bytes: vec![0xc0, 0x00],
+        });
+        assert!(m.check(&rdesc).is_err());
+        m.bytes.pop();
+        m.bytes.push(RdescBytes {
+            offset: usize::MAX,
+            bytes: vec![0x05],
+        });
+        assert!(m.check(&rdesc).is_err());
+        m.bytes.pop();
+
+        m.sha256 = vec![manifest::sha256(&rdesc)];
+        assert!(m.check(&rdesc).is_ok());
+        let mut other = rdesc;
+        other[5] = 0x02;
+        assert!(m.check(&other).is_err());
+    }
+
+    #[test]
+    fn test_device_match() {
+        let device = DeviceProperties {
+            name: String::from("Logitech USB Receiver Mouse"),
+            phys: String::from("usb-0000:00:14.0-2/input1"),
+            uniq: String::new(),
+            usb_interface: Some(1),
+        };
+
+        let mut m = DeviceMatch::default();
+        assert!(m.is_empty());
+        assert!(m.check(&device).is_ok());
+
+        m.names = vec![String::from("Logitech .* Mouse")];
+        assert!(m.check(&device).is_ok());
+        m.names = vec![String::from("Logitech"), String::from(".*Keyboard")];
+        assert!(m.check(&device).is_err());
+        m.names = vec![String::from("Logitech"), String::from(".*Mouse")];
+        assert!(m.check(&device).is_ok());
+
+        m.phys = vec![String::from(".*/input1")];
+        assert!(m.check(&device).is_ok());
+        m.phys = vec![String::from(".*/input0")];
+        assert!(m.check(&device).is_err());
+        m.phys.clear();
+
+        m.usb_interfaces = vec![0, 1];
+        assert!(m.check(&device).is_ok());
+        m.usb_interfaces = vec![2];
+        assert!(m.check(&device).is_err());
+        let bluetooth = DeviceProperties {
+            usb_interface: None,
+            ..device.clone()
+        };
+        assert!(m.check(&bluetooth).is_err());
+        m.usb_interfaces.clear();
+
+        m.uniq = vec![String::from("aa:bb:cc:dd:ee:ff")];
+        assert!(m.check(&device).is_err());
+        let bluetooth = DeviceProperties {
+            uniq: String::from("aa:bb:cc:dd:ee:ff"),
+            ..device
+        };
+        assert!(m.check(&bluetooth).is_ok());
+
+        m.names = vec![String::from("(unclosed")];
+        assert!(m.check(&bluetooth).is_err());
+    }
+
+    #[test]
+    fn test_conflicts_with() {
+        let info = ObjectInfo {
+            conflicts: vec![String::from("Huion__Dial-2")],
+            ..Default::default()
+        };
+        assert!(info.conflicts_with("huion__dial-2.bpf.o"));
+        assert!(!info.conflicts_with("huion__dial.bpf.o"));
+    }
+
+    /// A minimal little-endian ELF file with a .hid_bpf_config section
+    /// and a symbol table, as written by clang
+    fn elf_file(symbols: &[(&str, &[u8])]) -> Vec<u8> {
+        let shstrtab = b"\0.hid_bpf_config\0.symtab\0.strtab\0.shstrtab\0";
+        let mut config = Vec::new();
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 24];
+        for (name, value) in symbols {
+            let mut sym = Vec::new();
+            sym.extend((strtab.len() as u32).to_le_bytes());
+            sym.extend([0x11, 0]);
+            sym.extend(1u16.to_le_bytes());
+            sym.extend((config.len() as u64).to_le_bytes());
+            sym.extend((value.len() as u64).to_le_bytes());
+            symtab.extend(sym);
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+            config.extend(*value);
+        }
+
+        let mut data = vec![0u8; 64];
+        let mut sections = vec![(0, 0, 0, 0, 0)];
+        for (name, sh_type, content, link) in [
+            (1, 1, &config, 0),
+            (17, 2, &symtab, 3),
+            (25, 3, &strtab, 0),
+            (33, 3, &shstrtab.to_vec(), 0),
+        ] {
+            sections.push((name, sh_type, data.len(), content.len(), link));
+            data.extend(content);
+        }
+        let shoff = data.len();
+        for (name, sh_type, offset, size, link) in &sections {
+            let mut header = vec![0u8; 64];
+            header[0..4].copy_from_slice(&(*name as u32).to_le_bytes());
+            header[4..8].copy_from_slice(&(*sh_type as u32).to_le_bytes());
+            header[24..32].copy_from_slice(&(*offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(*size as u64).to_le_bytes());
+            header[40..44].copy_from_slice(&(*link as u32).to_le_bytes());
+            data.extend(header);
+        }
+        data[0..6].copy_from_slice(b"\x7fELF\x02\x01");
+        data[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3C..0x3E].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        data[0x3E..0x40].copy_from_slice(&4u16.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_section_symbols() {
+        let data = elf_file(&[
+            ("_hid_bpf_description", b"Fixes the Foo\0"),
+            ("_hid_bpf_rdesc_bytes_177__42", &[0xef, 0x00, 0x01]),
+            ("not_metadata", b"ignored\0"),
+        ]);
+        assert_eq!(
+            section_symbols(&data, CONFIG_SECTION).unwrap(),
+            vec![
+                (
+                    String::from("_hid_bpf_description"),
+                    b"Fixes the Foo\0".to_vec()
+                ),
+                (
+                    String::from("_hid_bpf_rdesc_bytes_177__42"),
+                    vec![0xef, 0x00, 0x01]
+                ),
+            ]
+        );
+        assert!(section_symbols(&data, ".other").unwrap().is_empty());
+        assert!(section_symbols(b"not an elf file", CONFIG_SECTION).is_err());
+        assert!(section_symbols(&data[..100], CONFIG_SECTION).is_err());
+
+        // a section header offset that overflows
+        let mut data = data;
+        data[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(section_symbols(&data, CONFIG_SECTION).is_err());
     }
 }
@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+include!(concat!(env!("OUT_DIR"), "/monitor.skel.rs"));
+
+use anyhow::{Context, Result};
+use libbpf_rs::skel::{OpenSkel, Skel, SkelBuilder};
+use libbpf_rs::RingBufferBuilder;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// See MONITOR_MAX_REPORT_SIZE in monitor.h
+const MONITOR_MAX_REPORT_SIZE: usize = 4096;
+
+/// The /dev/hidrawN node of the device at the given syspath
+pub fn hidraw_node(syspath: &Path) -> Result<PathBuf> {
+    let dir = syspath.join("hidraw");
+    std::fs::read_dir(&dir)
+        .context(format!("Failed to read {dir:?}, is hidraw enabled?"))?
+        .flatten()
+        .map(|entry| PathBuf::from("/dev").join(entry.file_name()))
+        .next()
+        .context(format!("No hidraw node found for {syspath:?}"))
+}
+
+/// An input report as sent by the device, before any HID-BPF program ran
+#[derive(Debug, PartialEq)]
+pub struct RawReport {
+    pub data: Vec<u8>,
+    /// The report was not forwarded to hidraw, e.g. because a
+    /// HID-BPF program returned an error for it
+    pub dropped: bool,
+}
+
+impl RawReport {
+    /// Parse a struct monitor_event as read from the ring buffer
+    fn parse(event: &[u8]) -> Option<Self> {
+        let size = u32::from_ne_bytes(event.get(0..4)?.try_into().ok()?) as usize;
+        let retval = i32::from_ne_bytes(event.get(4..8)?.try_into().ok()?);
+        let data = event.get(8..8 + size.min(MONITOR_MAX_REPORT_SIZE))?;
+        Some(RawReport {
+            data: data.to_vec(),
+            dropped: retval < 0,
+        })
+    }
+}
+
+/// Snapshots the input reports of one device before they are passed to
+/// the device's HID-BPF programs, using the bundled monitor.bpf.c tracing
+/// program.
+pub struct RawMonitor {
+    // Detaches the tracing program when dropped
+    _skel: MonitorSkel<'static>,
+    ringbuf: libbpf_rs::RingBuffer<'static>,
+    reports: Rc<RefCell<VecDeque<RawReport>>>,
+}
+
+impl RawMonitor {
+    pub fn new(hid_id: u32) -> Result<Self> {
+        let mut open_skel = MonitorSkelBuilder::default()
+            .open()
+            .context("Failed to open the monitor program")?;
+        open_skel.rodata_mut().hid_id = hid_id;
+        let mut skel = open_skel
+            .load()
+            .context("Failed to load the monitor program")?;
+        skel.attach()
+            .context("Failed to attach the monitor program")?;
+
+        let reports: Rc<RefCell<VecDeque<RawReport>>> = Rc::default();
+        let queue = Rc::clone(&reports);
+        let ringbuf = {
+            let maps = skel.maps();
+            let mut builder = RingBufferBuilder::new();
+            builder.add(maps.events(), move |event| {
+                if let Some(report) = RawReport::parse(event) {
+                    queue.borrow_mut().push_back(report);
+                }
+                0
+            })?;
+            builder.build()?
+        };
+
+        Ok(RawMonitor {
+            _skel: skel,
+            ringbuf,
+            reports,
+        })
+    }
+
+    /// A fd that is readable when reports are available
+    pub fn fd(&self) -> i32 {
+        self.ringbuf.epoll_fd()
+    }
+
+    /// The reports received since the last call, in the order they were sent
+    pub fn reports(&self) -> Result<Vec<RawReport>> {
+        self.ringbuf
+            .consume()
+            .context("Failed to read the monitor ring buffer")?;
+        Ok(self.reports.borrow_mut().drain(..).collect())
+    }
+}
+
+/// Format the field values of a report as decoded by
+/// ReportDescriptor::annotate(), showing the raw value of each field
+/// whose value was modified as `name: raw -> value`.
+pub fn format_fields(fields: &[(String, i64)], raw_fields: Option<&[(String, i64)]>) -> String {
+    let raw_value = |idx: usize, name: &str| {
+        raw_fields
+            .and_then(|raw| raw.get(idx))
+            .filter(|(raw_name, _)| raw_name == name)
+            .map(|(_, value)| *value)
+    };
+
+    let fields: Vec<String> = fields
+        .iter()
+        .enumerate()
+        .map(|(idx, (name, value))| match raw_value(idx, name) {
+            Some(raw) if raw != *value => format!("{name}: {raw} -> {value}"),
+            _ => format!("{name}: {value}"),
+        })
+        .collect();
+    fields.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_report() {
+        let mut event = Vec::new();
+        event.extend(3u32.to_ne_bytes());
+        event.extend(0i32.to_ne_bytes());
+        event.extend([0x01, 0x02, 0x03, 0xaa]);
+        assert_eq!(
+            RawReport::parse(&event),
+            Some(RawReport {
+                data: vec![0x01, 0x02, 0x03],
+                dropped: false,
+            })
+        );
+
+        event[4..8].copy_from_slice(&(-22i32).to_ne_bytes());
+        assert!(RawReport::parse(&event).unwrap().dropped);
+
+        assert_eq!(RawReport::parse(&event[..6]), None);
+    }
+
+    #[test]
+    fn test_format_fields() {
+        let fields = vec![(String::from("X"), 3), (String::from("Y"), -1)];
+        assert_eq!(format_fields(&fields, None), "X: 3, Y: -1");
+
+        let raw = vec![(String::from("X"), 3), (String::from("Y"), -2)];
+        assert_eq!(format_fields(&fields, Some(&raw)), "X: 3, Y: -2 -> -1");
+
+        // a different report, nothing to compare
+        let raw = vec![(String::from("Button 1"), 1)];
+        assert_eq!(format_fields(&fields, Some(&raw)), "X: 3, Y: -1");
+    }
+}
@@ -0,0 +1,515 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! bpf.o files are stored either as-is (0010-foo.bpf.o), compressed
+//! (0010-foo.bpf.o.zst, 0010-foo.bpf.o.xz) or as a member of a bundle
+//! (hid-bpf.bpf.bundle). A bundle member is addressed as if the bundle was
+//! a directory, e.g. /path/to/hid-bpf.bpf.bundle/0010-foo.bpf.o.
+
+use crate::modalias;
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub static OBJFILE_SUFFIX: &str = ".bpf.o";
+pub static BUNDLE_SUFFIX: &str = ".bpf.bundle";
+/// The suffix of the variant of an object built for the tracing backend
+/// of kernels without HID-BPF struct_ops, e.g. 0010-foo.legacy.bpf.o
+/// next to 0010-foo.bpf.o
+pub static LEGACY_SUFFIX: &str = ".legacy.bpf.o";
+
+/// The supported compression suffixes and their decoder. Both decoders
+/// are pure Rust so we don't depend on any tool or library being present
+/// in e.g. the initramfs.
+static COMPRESSION: [(&str, Decoder); 2] = [(".zst", decode_zstd), (".xz", decode_xz)];
+
+/// Decompress all of the reader into at most MAX_OBJFILE_SIZE bytes
+type Decoder = fn(&mut dyn std::io::BufRead) -> Result<Vec<u8>>;
+
+/// The maximum size of a bpf.o file after decompression or in a bundle.
+/// Real objects are a few hundred KB at most, this protects against
+/// allocating huge buffers for corrupt files.
+const MAX_OBJFILE_SIZE: u64 = 64 * 1024 * 1024;
+/// The maximum size of the index of a bundle
+const MAX_INDEX_SIZE: usize = 16 * 1024 * 1024;
+
+static BUNDLE_MAGIC: &[u8; 8] = b"HIDBPFB1";
+
+/// One object of a bundle
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BundleEntry {
+    /// The file name of the object, e.g. 0010-foo.bpf.o
+    pub name: String,
+    /// The modalias matches of this object, e.g. hid:b0003g*v0000256Cp0000006D
+    pub modaliases: Vec<String>,
+    /// The description, minimum kernel, etc. of the object
+    #[serde(default)]
+    pub info: modalias::ObjectInfo,
+    /// Offset of the object data from the end of the index
+    offset: u64,
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct BundleIndex {
+    objects: Vec<BundleEntry>,
+}
+
+/// A single file containing many bpf.o files. The file starts with
+/// BUNDLE_MAGIC, followed by the length of the index as le32, the
+/// index in JSON and finally the data of all objects.
+pub struct Bundle {
+    path: PathBuf,
+    pub objects: Vec<BundleEntry>,
+    data_offset: u64,
+}
+
+impl Bundle {
+    /// Open a bundle, reading only its index
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file =
+            std::fs::File::open(path).context(format!("Failed to open bundle {path:?}"))?;
+
+        let mut header = [0u8; 12];
+        file.read_exact(&mut header)
+            .context(format!("Failed to read bundle {path:?}"))?;
+        ensure!(&header[..8] == BUNDLE_MAGIC, "{path:?} is not a bundle");
+        let index_len = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+        ensure!(
+            index_len <= MAX_INDEX_SIZE,
+            "Index of bundle {path:?} is too large ({index_len} bytes)"
+        );
+
+        let mut index = vec![0u8; index_len];
+        file.read_exact(&mut index)
+            .context(format!("Failed to read the index of bundle {path:?}"))?;
+        let index: BundleIndex =
+            serde_json::from_slice(&index).context(format!("Invalid index in bundle {path:?}"))?;
+
+        Ok(Bundle {
+            path: PathBuf::from(path),
+            objects: index.objects,
+            data_offset: (header.len() + index_len) as u64,
+        })
+    }
+
+    /// The paths of all objects in this bundle
+    pub fn members(&self) -> Vec<PathBuf> {
+        self.objects
+            .iter()
+            .map(|entry| self.path.join(&entry.name))
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.objects.iter().any(|entry| entry.name == name)
+    }
+
+    pub fn read(&self, name: &str) -> Result<Vec<u8>> {
+        let Some(entry) = self.objects.iter().find(|entry| entry.name == name) else {
+            bail!("{name} is not in bundle {:?}", self.path);
+        };
+
+        let mut file = std::fs::File::open(&self.path)
+            .context(format!("Failed to open bundle {:?}", self.path))?;
+        ensure!(
+            entry.size <= MAX_OBJFILE_SIZE,
+            "{name} in bundle {:?} is too large ({} bytes)",
+            self.path,
+            entry.size
+        );
+        let start = self.data_offset.checked_add(entry.offset).context(format!(
+            "Invalid offset of {name} in bundle {:?}",
+            self.path
+        ))?;
+        let mut data = vec![0u8; entry.size as usize];
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_exact(&mut data))
+            .context(format!("Failed to read {name} from bundle {:?}", self.path))?;
+        Ok(data)
+    }
+
+    /// Write a bundle of the given objects, each a tuple of
+    /// (file name, modaliases, object info, object data)
+    pub fn write(
+        path: &Path,
+        objects: &[(String, Vec<String>, modalias::ObjectInfo, Vec<u8>)],
+    ) -> Result<()> {
+        let mut offset = 0;
+        let index = BundleIndex {
+            objects: objects
+                .iter()
+                .map(|(name, modaliases, info, data)| {
+                    let entry = BundleEntry {
+                        name: name.clone(),
+                        modaliases: modaliases.clone(),
+                        info: info.clone(),
+                        offset,
+                        size: data.len() as u64,
+                    };
+                    offset += data.len() as u64;
+                    entry
+                })
+                .collect(),
+        };
+        let index = serde_json::to_vec(&index).context("Failed to serialize the bundle index")?;
+
+        let mut file =
+            std::fs::File::create(path).context(format!("Failed to create bundle {path:?}"))?;
+        file.write_all(BUNDLE_MAGIC)
+            .and_then(|_| file.write_all(&(index.len() as u32).to_le_bytes()))
+            .and_then(|_| file.write_all(&index))
+            .and_then(|_| {
+                objects
+                    .iter()
+                    .try_for_each(|(_, _, _, data)| file.write_all(data))
+            })
+            .context(format!("Failed to write bundle {path:?}"))
+    }
+}
+
+fn is_bundle(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(BUNDLE_SUFFIX) && path.is_file()
+}
+
+/// The bundle a path refers to a member of, if any
+fn bundle_of(path: &Path) -> Option<(Bundle, String)> {
+    let parent = path.parent().filter(|p| is_bundle(p))?;
+    let name = String::from(path.file_name()?.to_string_lossy());
+    Bundle::open(parent)
+        .ok()
+        .filter(|bundle| bundle.contains(&name))
+        .map(|bundle| (bundle, name))
+}
+
+/// The index entry of a bundle member: its modaliases and metadata
+/// without reading the member itself. None if the path is not a
+/// member of a bundle.
+pub fn bundle_entry(path: &Path) -> Option<BundleEntry> {
+    let (bundle, name) = bundle_of(path)?;
+    bundle.objects.into_iter().find(|entry| entry.name == name)
+}
+
+/// The file name of a bpf.o file without any compression suffix,
+/// e.g. 0010-foo.bpf.o for /path/to/0010-foo.bpf.o.zst
+pub fn filename(path: &Path) -> String {
+    let filename = String::from(path.file_name().unwrap_or_default().to_string_lossy());
+    COMPRESSION
+        .iter()
+        .find_map(|(suffix, _)| filename.strip_suffix(suffix))
+        .map(String::from)
+        .unwrap_or(filename)
+}
+
+/// The object name libbpf and our bpffs paths use, e.g. 0010-foo.bpf
+pub fn object_name(path: &Path) -> String {
+    let filename = filename(path);
+    filename
+        .strip_suffix(".o")
+        .map(String::from)
+        .unwrap_or(filename)
+}
+
+/// Whether the path has the name of a (compressed) bpf.o file
+pub fn is_objfile(path: &Path) -> bool {
+    filename(path).ends_with(OBJFILE_SUFFIX)
+}
+
+/// The variants of the bpf.o file: the file itself followed by its
+/// LEGACY_SUFFIX variant in the same directory or bundle, if any
+pub fn variants(path: &Path) -> Vec<PathBuf> {
+    let legacy = filename(path)
+        .strip_suffix(OBJFILE_SUFFIX)
+        .filter(|name| !name.ends_with(".legacy"))
+        .and_then(|name| find_in_dir(path.parent()?, &format!("{name}{LEGACY_SUFFIX}")));
+    std::iter::once(PathBuf::from(path)).chain(legacy).collect()
+}
+
+/// The bpf.o file the path is the LEGACY_SUFFIX variant of, if that file
+/// exists in the same directory or bundle
+pub fn variant_base(path: &Path) -> Option<PathBuf> {
+    let name = filename(path);
+    let name = name.strip_suffix(LEGACY_SUFFIX)?;
+    find_in_dir(path.parent()?, &format!("{name}{OBJFILE_SUFFIX}"))
+}
+
+/// Whether the path is an existing bpf.o file or bundle member
+pub fn exists(path: &Path) -> bool {
+    path.is_file() || bundle_of(path).is_some()
+}
+
+/// All bpf.o files in the given file: the file itself if it is a bpf.o
+/// file or all members if it is a bundle
+pub fn objfiles_in_file(path: &Path) -> Result<Vec<PathBuf>> {
+    if is_objfile(path) {
+        Ok(vec![PathBuf::from(path)])
+    } else if is_bundle(path) {
+        Ok(Bundle::open(path)?.members())
+    } else {
+        bail!("Not a bpf.o file or bundle");
+    }
+}
+
+/// All bpf.o files in the given file or, recursively, directory
+pub fn find_all(path: &Path) -> Result<Vec<PathBuf>> {
+    ensure!(path.exists(), "File or directory {path:?} does not exist");
+
+    if path.is_file() {
+        objfiles_in_file(path)
+    } else {
+        Ok(std::fs::read_dir(path)?
+            .flatten()
+            .flat_map(|f| find_all(&f.path()))
+            .flatten()
+            .collect())
+    }
+}
+
+/// Find the bpf.o file with the given file name in the directory: the file
+/// itself, a compressed file or a member of a bundle in that directory.
+/// An absolute filename is looked up in its own directory.
+pub fn find_in_dir(dir: &Path, filename: &str) -> Option<PathBuf> {
+    let path = dir.join(filename);
+    if exists(&path) {
+        return Some(path);
+    }
+
+    let compressed = COMPRESSION
+        .iter()
+        .map(|(suffix, _)| {
+            let mut compressed = path.clone().into_os_string();
+            compressed.push(suffix);
+            PathBuf::from(compressed)
+        })
+        .find(|p| p.is_file());
+    if compressed.is_some() {
+        return compressed;
+    }
+
+    let name = path.file_name()?.to_string_lossy();
+    let mut bundles: Vec<PathBuf> = std::fs::read_dir(path.parent()?)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| is_bundle(p))
+        .collect();
+    bundles.sort();
+    bundles
+        .into_iter()
+        .find(|p| Bundle::open(p).is_ok_and(|bundle| bundle.contains(&name)))
+        .map(|p| p.join(name.as_ref()))
+}
+
+/// Read all of the reader, failing if it has more than MAX_OBJFILE_SIZE bytes
+fn read_limited(reader: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(MAX_OBJFILE_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_OBJFILE_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("decompressed data exceeds {MAX_OBJFILE_SIZE} bytes"),
+        ));
+    }
+    Ok(data)
+}
+
+fn decode_zstd(input: &mut dyn std::io::BufRead) -> Result<Vec<u8>> {
+    let decoder = ruzstd::StreamingDecoder::new(input)?;
+    Ok(read_limited(decoder)?)
+}
+
+/// A writer that fails once more than MAX_OBJFILE_SIZE bytes are written,
+/// lzma-rs writes the decompressed data instead of providing a reader
+struct LimitedWriter(Vec<u8>);
+
+impl Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if (self.0.len() + buf.len()) as u64 > MAX_OBJFILE_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("decompressed data exceeds {MAX_OBJFILE_SIZE} bytes"),
+            ));
+        }
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn decode_xz(mut input: &mut dyn std::io::BufRead) -> Result<Vec<u8>> {
+    let mut output = LimitedWriter(Vec::new());
+    lzma_rs::xz_decompress(&mut input, &mut output)?;
+    Ok(output.0)
+}
+
+fn decompress(path: &Path, decode: Decoder) -> Result<Vec<u8>> {
+    let file = std::fs::File::open(path).context(format!("Failed to open {path:?}"))?;
+    decode(&mut std::io::BufReader::new(file)).context(format!("Failed to decompress {path:?}"))
+}
+
+/// The contents of a bpf.o file, decompressed or extracted from its bundle
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    if let Some((bundle, name)) = bundle_of(path) {
+        return bundle.read(&name);
+    }
+
+    let filename = String::from(path.file_name().unwrap_or_default().to_string_lossy());
+    match COMPRESSION
+        .iter()
+        .find(|(suffix, _)| filename.ends_with(suffix))
+    {
+        Some((_, decode)) => decompress(path, *decode),
+        None => std::fs::read(path).context(format!("Failed to read {path:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filename() {
+        let path = PathBuf::from("/usr/lib/firmware/hid/bpf/0010-foo.bpf.o.zst");
+        assert_eq!(filename(&path), "0010-foo.bpf.o");
+        assert_eq!(object_name(&path), "0010-foo.bpf");
+        assert!(is_objfile(&path));
+
+        let path = PathBuf::from("/usr/lib/firmware/hid/bpf/hid-bpf.bpf.bundle/0010-foo.bpf.o");
+        assert_eq!(filename(&path), "0010-foo.bpf.o");
+        assert!(is_objfile(&path));
+
+        assert!(!is_objfile(&PathBuf::from("0010-foo.bpf.c")));
+        assert!(!is_objfile(&PathBuf::from("0010-foo.bpf.o.gz")));
+    }
+
+    #[test]
+    fn test_bundle() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("hid-bpf.bpf.bundle");
+        let objects = vec![
+            (
+                String::from("0010-one.bpf.o"),
+                vec![String::from("hid:b0003g*v0000256Cp0000006D")],
+                modalias::ObjectInfo {
+                    description: Some(String::from("One")),
+                    ..Default::default()
+                },
+                vec![1, 2, 3],
+            ),
+            (
+                String::from("0010-two.bpf.o"),
+                vec![],
+                modalias::ObjectInfo::default(),
+                vec![4, 5],
+            ),
+        ];
+        Bundle::write(&path, &objects).unwrap();
+
+        let bundle = Bundle::open(&path).unwrap();
+        assert_eq!(
+            bundle.members(),
+            vec![path.join("0010-one.bpf.o"), path.join("0010-two.bpf.o")]
+        );
+        assert_eq!(
+            bundle.objects[0].modaliases,
+            vec!["hid:b0003g*v0000256Cp0000006D"]
+        );
+        let entry = bundle_entry(&path.join("0010-one.bpf.o")).unwrap();
+        assert_eq!(entry.info.description.as_deref(), Some("One"));
+        assert_eq!(bundle_entry(&path.join("0010-three.bpf.o")), None);
+        assert!(bundle.read("0010-three.bpf.o").is_err());
+
+        assert!(exists(&path.join("0010-two.bpf.o")));
+        assert!(!exists(&path.join("0010-three.bpf.o")));
+        assert_eq!(read(&path.join("0010-one.bpf.o")).unwrap(), vec![1, 2, 3]);
+        assert_eq!(read(&path.join("0010-two.bpf.o")).unwrap(), vec![4, 5]);
+
+        assert_eq!(
+            find_in_dir(tmpdir.path(), "0010-two.bpf.o"),
+            Some(path.join("0010-two.bpf.o"))
+        );
+        assert_eq!(find_in_dir(tmpdir.path(), "0010-three.bpf.o"), None);
+
+        assert_eq!(
+            variants(&path.join("0010-one.bpf.o")),
+            vec![path.join("0010-one.bpf.o")]
+        );
+        assert_eq!(variant_base(&path.join("0010-two.bpf.o")), None);
+
+        // a file next to the bundle takes precedence
+        let file = tmpdir.path().join("0010-two.bpf.o");
+        std::fs::write(&file, [6]).unwrap();
+        assert_eq!(find_in_dir(tmpdir.path(), "0010-two.bpf.o"), Some(file));
+
+        assert!(Bundle::open(&tmpdir.path().join("0010-two.bpf.o")).is_err());
+    }
+
+    #[test]
+    fn test_decompress() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..200).map(|i| (i % 7) as u8).collect();
+
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut data.as_slice(), &mut xz).unwrap();
+        let path = tmpdir.path().join("0010-foo.bpf.o.xz");
+        std::fs::write(&path, xz).unwrap();
+        assert_eq!(read(&path).unwrap(), data);
+
+        // A single segment frame with one raw block
+        let mut zst: Vec<u8> = vec![0x28, 0xb5, 0x2f, 0xfd, 0x20, data.len() as u8];
+        zst.extend_from_slice(&(1 | (data.len() as u32) << 3).to_le_bytes()[..3]);
+        zst.extend_from_slice(&data);
+        let path = tmpdir.path().join("0010-bar.bpf.o.zst");
+        std::fs::write(&path, zst).unwrap();
+        assert_eq!(read(&path).unwrap(), data);
+        assert_eq!(
+            find_in_dir(tmpdir.path(), "0010-bar.bpf.o"),
+            Some(path.clone())
+        );
+
+        std::fs::write(&path, [1, 2, 3]).unwrap();
+        assert!(read(&path).is_err());
+
+        let path = tmpdir.path().join("huge.bpf.bundle");
+        let mut bundle = BUNDLE_MAGIC.to_vec();
+        bundle.extend_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, bundle).unwrap();
+        assert!(Bundle::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_variants() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = tmpdir.path();
+        for name in [
+            "0010-one.bpf.o",
+            "0010-one.legacy.bpf.o.zst",
+            "0010-two.legacy.bpf.o",
+        ] {
+            std::fs::write(dir.join(name), []).unwrap();
+        }
+
+        assert_eq!(
+            variants(&dir.join("0010-one.bpf.o")),
+            vec![
+                dir.join("0010-one.bpf.o"),
+                dir.join("0010-one.legacy.bpf.o.zst")
+            ]
+        );
+        assert_eq!(
+            variant_base(&dir.join("0010-one.legacy.bpf.o.zst")),
+            Some(dir.join("0010-one.bpf.o"))
+        );
+        assert_eq!(variant_base(&dir.join("0010-one.bpf.o")), None);
+
+        // a legacy variant without its base is an object on its own
+        assert_eq!(
+            variants(&dir.join("0010-two.legacy.bpf.o")),
+            vec![dir.join("0010-two.legacy.bpf.o")]
+        );
+        assert_eq!(variant_base(&dir.join("0010-two.legacy.bpf.o")), None);
+    }
+}
@@ -0,0 +1,341 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// One field of an input report
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub report_id: u8,
+    /// Offset in bits from the start of the report, excluding the report ID
+    pub bit_offset: usize,
+    pub bit_size: usize,
+    /// The usage page in the high 16 bits, the usage ID in the low 16 bits
+    pub usage: u32,
+    /// Field is a variable, i.e. its value is the value of its usage.
+    /// Otherwise the field is an array whose value is an index.
+    pub is_variable: bool,
+    pub is_signed: bool,
+    pub is_const: bool,
+}
+
+/// The input fields of a HID report descriptor
+#[derive(Debug, Default)]
+pub struct ReportDescriptor {
+    pub fields: Vec<Field>,
+    pub uses_report_ids: bool,
+}
+
+/// The kernel's HID_MAX_USAGES, the kernel rejects a larger report count
+/// and ignores any usages past this many
+const MAX_USAGES: usize = 12288;
+
+#[derive(Clone, Default)]
+struct Globals {
+    usage_page: u32,
+    logical_minimum: i32,
+    report_size: usize,
+    report_count: usize,
+    report_id: u8,
+}
+
+fn signed(data: &[u8]) -> i32 {
+    match data.len() {
+        1 => data[0] as i8 as i32,
+        2 => i16::from_le_bytes([data[0], data[1]]) as i32,
+        4 => i32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+        _ => 0,
+    }
+}
+
+fn unsigned(data: &[u8]) -> u32 {
+    data.iter()
+        .rev()
+        .fold(0, |acc, b| (acc << 8) | u32::from(*b))
+}
+
+impl ReportDescriptor {
+    pub fn parse(rdesc: &[u8]) -> Result<Self> {
+        let mut result = ReportDescriptor::default();
+        let mut globals = Globals::default();
+        let mut stack: Vec<Globals> = Vec::new();
+        let mut usages: Vec<u32> = Vec::new();
+        let mut usage_minimum: Option<u32> = None;
+        let mut offsets: HashMap<u8, usize> = HashMap::new();
+
+        let mut idx = 0;
+        while idx < rdesc.len() {
+            let prefix = rdesc[idx];
+            // Long items have no use in practice, skip them
+            if prefix == 0xfe {
+                let Some(size) = rdesc.get(idx + 1) else {
+                    bail!("Truncated long item at offset {idx}");
+                };
+                idx += 3 + *size as usize;
+                continue;
+            }
+
+            let size = match prefix & 0x3 {
+                3 => 4,
+                s => s as usize,
+            };
+            let Some(data) = rdesc.get(idx + 1..idx + 1 + size) else {
+                bail!("Truncated item at offset {idx}");
+            };
+            idx += 1 + size;
+
+            // A usage with 4 bytes of data includes the usage page
+            let usage = |data: &[u8]| {
+                if data.len() == 4 {
+                    unsigned(data)
+                } else {
+                    (globals.usage_page << 16) | unsigned(data)
+                }
+            };
+
+            match prefix & 0xfc {
+                // Input
+                0x80 => {
+                    let flags = unsigned(data);
+                    let is_const = flags & 0x1 != 0;
+                    let is_variable = flags & 0x2 != 0;
+                    if globals.report_count > MAX_USAGES {
+                        bail!("Invalid report count {}", globals.report_count);
+                    }
+                    let offset = offsets.entry(globals.report_id).or_default();
+                    if let Some(minimum) = usage_minimum {
+                        if usages.len() == 1 {
+                            let maximum = usages[0];
+                            usages = (minimum..=maximum).take(MAX_USAGES).collect();
+                        }
+                    }
+                    for i in 0..globals.report_count {
+                        let usage = if is_variable {
+                            usages.get(i).or(usages.last())
+                        } else {
+                            usages.first()
+                        };
+                        result.fields.push(Field {
+                            report_id: globals.report_id,
+                            bit_offset: *offset,
+                            bit_size: globals.report_size,
+                            usage: usage.copied().unwrap_or(0),
+                            is_variable,
+                            is_signed: globals.logical_minimum < 0,
+                            is_const,
+                        });
+                        *offset += globals.report_size;
+                    }
+                }
+                // Output, Feature: don't contribute to input reports
+                0x90 | 0xb0 => {}
+                // Collection, End Collection
+                0xa0 | 0xc0 => {}
+                // Usage Page
+                0x04 => globals.usage_page = unsigned(data),
+                // Logical Minimum
+                0x14 => globals.logical_minimum = signed(data),
+                // Report Size
+                0x74 => globals.report_size = unsigned(data) as usize,
+                // Report ID
+                0x84 => {
+                    globals.report_id = unsigned(data) as u8;
+                    result.uses_report_ids = true;
+                }
+                // Report Count
+                0x94 => globals.report_count = unsigned(data) as usize,
+                // Push
+                0xa4 => stack.push(globals.clone()),
+                // Pop
+                0xb4 => {
+                    let Some(g) = stack.pop() else {
+                        bail!("Pop without Push at offset {idx}");
+                    };
+                    globals = g;
+                }
+                // Usage
+                0x08 => usages.push(usage(data)),
+                // Usage Minimum
+                0x18 => usage_minimum = Some(usage(data)),
+                // Usage Maximum
+                0x28 => usages.push(usage(data)),
+                _ => {}
+            }
+
+            // Local items only apply to the next main item
+            if prefix & 0x0c == 0x00 {
+                usages.clear();
+                usage_minimum = None;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Return the name and value of each non-constant field in the given report
+    pub fn annotate(&self, report: &[u8]) -> Vec<(String, i64)> {
+        let (report_id, data) = match (self.uses_report_ids, report.split_first()) {
+            (true, Some((id, data))) => (*id, data),
+            _ => (0, report),
+        };
+
+        self.fields
+            .iter()
+            .filter(|f| f.report_id == report_id && !f.is_const)
+            .filter(|f| f.bit_offset + f.bit_size <= data.len() * 8)
+            .map(|f| {
+                let value = extract(data, f.bit_offset, f.bit_size, f.is_signed);
+                let name = if f.is_variable {
+                    usage_name(f.usage)
+                } else {
+                    format!("{}[]", usage_name(f.usage))
+                };
+                (name, value)
+            })
+            .collect()
+    }
+}
+
+fn extract(data: &[u8], bit_offset: usize, bit_size: usize, is_signed: bool) -> i64 {
+    let value = (0..bit_size.min(63)).fold(0u64, |acc, bit| {
+        let pos = bit_offset + bit;
+        let set = (data[pos / 8] >> (pos % 8)) & 0x1;
+        acc | (u64::from(set) << bit)
+    });
+    if is_signed && bit_size > 0 && bit_size < 64 && value & (1 << (bit_size - 1)) != 0 {
+        value as i64 - (1 << bit_size)
+    } else {
+        value as i64
+    }
+}
+
+// The USAGE_NAMES and GENERATED_USAGE_NAMES tables, generated
+// by build.rs from data/HidUsageTables.json
+include!(concat!(env!("OUT_DIR"), "/usages.rs"));
+
+pub fn usage_name(usage: u32) -> String {
+    let page = (usage >> 16) as u16;
+    let id = (usage & 0xffff) as u16;
+
+    let name = USAGE_NAMES
+        .binary_search_by_key(&page, |(page, _)| *page)
+        .ok()
+        .and_then(|idx| {
+            let usages = USAGE_NAMES[idx].1;
+            let idx = usages.binary_search_by_key(&id, |(id, _)| *id).ok()?;
+            Some(usages[idx].1)
+        });
+    if let Some(name) = name {
+        return String::from(name);
+    }
+
+    if let Ok(idx) = GENERATED_USAGE_NAMES.binary_search_by_key(&page, |(page, _)| *page) {
+        return format!("{} {id}", GENERATED_USAGE_NAMES[idx].1);
+    }
+
+    match page {
+        0xff00..=0xffff => format!("Vendor {page:04x}:{id:04x}"),
+        _ => format!("{page:04x}:{id:04x}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A three button mouse with report ID 1
+    static MOUSE: &[u8] = &[
+        0x05, 0x01, // Usage Page (Generic Desktop)
+        0x09, 0x02, // Usage (Mouse)
+        0xa1, 0x01, // Collection (Application)
+        0x85, 0x01, //  Report ID (1)
+        0x09, 0x01, //  Usage (Pointer)
+        0xa1, 0x00, //  Collection (Physical)
+        0x05, 0x09, //   Usage Page (Button)
+        0x19, 0x01, //   Usage Minimum (1)
+        0x29, 0x03, //   Usage Maximum (3)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x95, 0x03, //   Report Count (3)
+        0x75, 0x01, //   Report Size (1)
+        0x81, 0x02, //   Input (Data,Var,Abs)
+        0x95, 0x01, //   Report Count (1)
+        0x75, 0x05, //   Report Size (5)
+        0x81, 0x03, //   Input (Cnst,Var,Abs)
+        0x05, 0x01, //   Usage Page (Generic Desktop)
+        0x09, 0x30, //   Usage (X)
+        0x09, 0x31, //   Usage (Y)
+        0x09, 0x38, //   Usage (Wheel)
+        0x15, 0x81, //   Logical Minimum (-127)
+        0x25, 0x7f, //   Logical Maximum (127)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x03, //   Report Count (3)
+        0x81, 0x06, //   Input (Data,Var,Rel)
+        0xc0, //        End Collection
+        0xc0, //       End Collection
+    ];
+
+    #[test]
+    fn test_parse() {
+        let rdesc = ReportDescriptor::parse(MOUSE).unwrap();
+        assert!(rdesc.uses_report_ids);
+        let fields: Vec<(usize, usize, String, bool)> = rdesc
+            .fields
+            .iter()
+            .map(|f| (f.bit_offset, f.bit_size, usage_name(f.usage), f.is_const))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                (0, 1, String::from("Button 1"), false),
+                (1, 1, String::from("Button 2"), false),
+                (2, 1, String::from("Button 3"), false),
+                (3, 5, String::from("0000:0000"), true),
+                (8, 8, String::from("X"), false),
+                (16, 8, String::from("Y"), false),
+                (24, 8, String::from("Wheel"), false),
+            ]
+        );
+
+        assert!(ReportDescriptor::parse(&[0x05]).is_err());
+
+        // Usage Minimum (0), Usage Maximum (0xffffffff), Report Count (1), Input
+        let rdesc = ReportDescriptor::parse(&[
+            0x19, 0x00, 0x2b, 0xff, 0xff, 0xff, 0xff, 0x95, 0x01, 0x81, 0x02,
+        ])
+        .unwrap();
+        assert_eq!(rdesc.fields.len(), 1);
+        // Report Count (0xffffffff), Input
+        assert!(ReportDescriptor::parse(&[0x97, 0xff, 0xff, 0xff, 0xff, 0x81, 0x02]).is_err());
+    }
+
+    #[test]
+    fn test_usage_name() {
+        assert_eq!(usage_name(0x000d_0030), "Tip Pressure");
+        assert_eq!(usage_name(0x0007_0004), "Keyboard A");
+        assert_eq!(usage_name(0x0009_0005), "Button 5");
+        assert_eq!(usage_name(0xff00_0001), "Vendor ff00:0001");
+        assert_eq!(usage_name(0x0001_ffff), "0001:ffff");
+    }
+
+    #[test]
+    fn test_annotate() {
+        let rdesc = ReportDescriptor::parse(MOUSE).unwrap();
+        let values = rdesc.annotate(&[0x01, 0x05, 0x03, 0xfe, 0x00]);
+        let values: Vec<(&str, i64)> = values.iter().map(|(n, v)| (n.as_str(), *v)).collect();
+        assert_eq!(
+            values,
+            vec![
+                ("Button 1", 1),
+                ("Button 2", 0),
+                ("Button 3", 1),
+                ("X", 3),
+                ("Y", -2),
+                ("Wheel", 0),
+            ]
+        );
+
+        // wrong report ID
+        assert!(rdesc.annotate(&[0x02, 0x05, 0x03, 0xfe, 0x00]).is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Every stem group of every device has a loaded bpf.o file
+pub const EXIT_SUCCESS: u8 = 0;
+/// No bpf.o file was needed: no file matched the device, all files' probes
+/// or metadata rejected the device or there was nothing to remove
+pub const EXIT_NOTHING_TO_DO: u8 = 3;
+/// At least one stem group has no loaded bpf.o file because loading failed
+pub const EXIT_LOAD_FAILED: u8 = 4;
+/// Loading failed because the kernel does not support HID-BPF
+pub const EXIT_UNSUPPORTED: u8 = 5;
+
+/// The outcome of loading one bpf.o file for a device
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Loaded,
+    ProbeRejected,
+    VerifierRejected,
+    Unsupported,
+    AttachFailed,
+    PinFailed,
+    LoadFailed,
+    /// The file is not signed by a trusted key, see signature.rs
+    SignatureRejected,
+    /// The running kernel is older than the file's HID_BPF_MIN_KERNEL
+    KernelTooOld,
+    /// The file conflicts with a file already loaded for the device,
+    /// see HID_BPF_CONFLICTS
+    Conflict,
+    /// The device's report descriptor does not match the file's
+    /// HID_BPF_RDESC_* metadata
+    RdescMismatch,
+    /// The device's name, phys, uniq or USB interface does not match the
+    /// file's metadata, see HID_BPF_NAME
+    DeviceMismatch,
+    /// The file's HID_BPF_CONFIG metadata could not be read, so none of
+    /// the checks above can be done
+    InvalidMetadata,
+    /// The file is stackable but none of the files it extends was loaded,
+    /// see HID_BPF_STACKABLE
+    BaseNotLoaded,
+}
+
+impl Outcome {
+    /// The stage of loading a bpf.o file that results in this outcome
+    pub fn stage(&self) -> &'static str {
+        match self {
+            Outcome::Loaded => "done",
+            Outcome::VerifierRejected | Outcome::Unsupported | Outcome::LoadFailed => "load",
+            Outcome::ProbeRejected => "probe",
+            Outcome::AttachFailed => "attach",
+            Outcome::PinFailed => "pin",
+            Outcome::SignatureRejected => "signature",
+            Outcome::KernelTooOld
+            | Outcome::Conflict
+            | Outcome::RdescMismatch
+            | Outcome::DeviceMismatch
+            | Outcome::InvalidMetadata
+            | Outcome::BaseNotLoaded => "metadata",
+        }
+    }
+
+    /// The file was not loaded because the device or system does
+    /// not need it, this is not a failure
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self,
+            Outcome::ProbeRejected
+                | Outcome::KernelTooOld
+                | Outcome::Conflict
+                | Outcome::RdescMismatch
+                | Outcome::DeviceMismatch
+                | Outcome::BaseNotLoaded
+        )
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The files tried for one stem group (e.g. 0020-foo.bpf.o, 0010-foo.bpf.o),
+/// in the order they were tried. At most one file of a group is loaded
+/// unless the loaded files are stackable, see HID_BPF_STACKABLE.
+#[derive(Serialize, Debug)]
+pub struct GroupReport {
+    pub stem: String,
+    pub files: Vec<FileReport>,
+    /// The file loaded, the base of the stacked files
+    pub winner: Option<PathBuf>,
+    /// The stackable files loaded after the winner to extend it,
+    /// in the order they were attached in
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stacked: Vec<PathBuf>,
+}
+
+impl GroupReport {
+    /// A group failed if no file was loaded for a reason other
+    /// than the device not needing any of the files.
+    fn failed(&self) -> bool {
+        self.winner.is_none() && self.files.iter().any(|f| !f.outcome.is_rejection())
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeviceReport {
+    pub sysname: String,
+    pub syspath: PathBuf,
+    /// The device has HID_BPF_IGNORE_DEVICE set
+    pub ignored: bool,
+    pub groups: Vec<GroupReport>,
+    /// Why the device could not be opened, no files were tried for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The result of the add command
+#[derive(Serialize, Debug, Default)]
+pub struct AddReport {
+    pub devices: Vec<DeviceReport>,
+}
+
+impl AddReport {
+    pub fn exit_code(&self) -> u8 {
+        let groups: Vec<&GroupReport> = self.devices.iter().flat_map(|d| &d.groups).collect();
+
+        let failures: Vec<Outcome> = groups
+            .iter()
+            .filter(|g| g.failed())
+            .flat_map(|g| &g.files)
+            .map(|f| f.outcome)
+            .filter(|o| !o.is_rejection())
+            .collect();
+
+        if !failures.is_empty() {
+            if failures.iter().all(|o| *o == Outcome::Unsupported) {
+                EXIT_UNSUPPORTED
+            } else {
+                EXIT_LOAD_FAILED
+            }
+        } else if groups.iter().any(|g| g.winner.is_some()) {
+            EXIT_SUCCESS
+        } else {
+            EXIT_NOTHING_TO_DO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(outcomes: &[Outcome]) -> GroupReport {
+        let files: Vec<FileReport> = outcomes
+            .iter()
+            .enumerate()
+            .map(|(idx, outcome)| FileReport {
+                path: PathBuf::from(format!("00{idx}0-foo.bpf.o")),
+                outcome: *outcome,
+                error: None,
+            })
+            .collect();
+        let winner = files
+            .iter()
+            .find(|f| f.outcome == Outcome::Loaded)
+            .map(|f| f.path.clone());
+        GroupReport {
+            stem: "foo.bpf.o".into(),
+            files,
+            winner,
+            stacked: Vec::new(),
+        }
+    }
+
+    fn report(groups: Vec<GroupReport>) -> AddReport {
+        AddReport {
+            devices: vec![DeviceReport {
+                sysname: "0003:045E:07A5.000B".into(),
+                syspath: "/sys/bus/hid/devices/0003:045E:07A5.000B".into(),
+                ignored: false,
+                groups,
+                error: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(report(vec![]).exit_code(), EXIT_NOTHING_TO_DO);
+
+        let r = report(vec![group(&[
+            Outcome::ProbeRejected,
+            Outcome::ProbeRejected,
+        ])]);
+        assert_eq!(r.exit_code(), EXIT_NOTHING_TO_DO);
+
+        let r = report(vec![group(&[Outcome::VerifierRejected, Outcome::Loaded])]);
+        assert_eq!(r.exit_code(), EXIT_SUCCESS);
+
+        let r = report(vec![
+            group(&[Outcome::Loaded]),
+            group(&[Outcome::ProbeRejected]),
+        ]);
+        assert_eq!(r.exit_code(), EXIT_SUCCESS);
+
+        let r = report(vec![
+            group(&[Outcome::Loaded]),
+            group(&[Outcome::ProbeRejected, Outcome::AttachFailed]),
+        ]);
+        assert_eq!(r.exit_code(), EXIT_LOAD_FAILED);
+
+        let r = report(vec![group(&[Outcome::Unsupported, Outcome::Unsupported])]);
+        assert_eq!(r.exit_code(), EXIT_UNSUPPORTED);
+
+        let r = report(vec![group(&[Outcome::Unsupported, Outcome::PinFailed])]);
+        assert_eq!(r.exit_code(), EXIT_LOAD_FAILED);
+
+        let r = report(vec![
+            group(&[Outcome::Loaded]),
+            group(&[Outcome::KernelTooOld, Outcome::Conflict]),
+            group(&[Outcome::RdescMismatch, Outcome::DeviceMismatch]),
+        ]);
+        assert_eq!(r.exit_code(), EXIT_SUCCESS);
+    }
+}
@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+include!(concat!(env!("OUT_DIR"), "/request.skel.rs"));
+
+use crate::bpf;
+use anyhow::{bail, Context, Result};
+use libbpf_rs::skel::{OpenSkel, SkelBuilder};
+
+/// See REQUEST_MAX_SIZE in request.h
+pub const REQUEST_MAX_SIZE: usize = 4096;
+
+/// enum hid_class_request
+const HID_REQ_GET_REPORT: u32 = 1;
+const HID_REQ_SET_REPORT: u32 = 9;
+
+/// The request to send to a device
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Request {
+    /// A GET_REPORT request, reads a report from the device
+    GetReport,
+    /// A SET_REPORT request, sends a report to the device
+    SetReport,
+    /// An output report sent on the interrupt endpoint
+    OutputReport,
+}
+
+/// enum hid_report_type
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ReportType {
+    Input = 0,
+    Output = 1,
+    Feature = 2,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct RequestArgs {
+    data: [u8; REQUEST_MAX_SIZE],
+    hid: u32,
+    size: u32,
+    report_type: u32,
+    request_type: u32,
+    retval: i32,
+}
+
+impl RequestArgs {
+    fn new(hid: u32, data: &[u8], size: usize) -> Result<Self> {
+        if size > REQUEST_MAX_SIZE {
+            bail!("Reports are limited to {REQUEST_MAX_SIZE} bytes");
+        }
+        if data.len() > size {
+            bail!(
+                "The data is {} bytes, longer than the size of {size} bytes",
+                data.len()
+            );
+        }
+        let mut args = RequestArgs {
+            data: [0; REQUEST_MAX_SIZE],
+            hid,
+            size: size as u32,
+            report_type: 0,
+            request_type: 0,
+            retval: -1,
+        };
+        args.data[..data.len()].copy_from_slice(data);
+        Ok(args)
+    }
+}
+
+/// Sends requests to a device through the hid_bpf_hw_request() and
+/// hid_bpf_hw_output_report() kfuncs, using the bundled request.bpf.c
+/// syscall programs.
+pub struct HidRequest<'a> {
+    skel: RequestSkel<'a>,
+}
+
+impl<'a> HidRequest<'a> {
+    pub fn new() -> Result<Self> {
+        let skel = RequestSkelBuilder::default()
+            .open()
+            .context("Failed to open the request program")?
+            .load()
+            .context("Failed to load the request program")?;
+        Ok(HidRequest { skel })
+    }
+
+    fn run(&self, prog: &libbpf_rs::Program, args: RequestArgs) -> Result<RequestArgs> {
+        let args = bpf::run_syscall_prog_generic(prog, args)
+            .context(format!("Failed to run {}", prog.name()))?;
+        if args.retval < 0 {
+            return Err(std::io::Error::from_raw_os_error(-args.retval))
+                .context(format!("Request to device {:04X} failed", args.hid));
+        }
+        Ok(args)
+    }
+
+    /// Issue a GET_REPORT request for the report whose ID is the first
+    /// byte of data, reading up to size bytes. Returns the report as
+    /// sent by the device.
+    pub fn get_report(
+        &self,
+        hid: u32,
+        report_type: ReportType,
+        data: &[u8],
+        size: usize,
+    ) -> Result<Vec<u8>> {
+        let mut args = RequestArgs::new(hid, data, size)?;
+        args.report_type = report_type as u32;
+        args.request_type = HID_REQ_GET_REPORT;
+        let args = self.run(self.skel.progs().hw_request(), args)?;
+        let len = (args.retval as usize).min(size);
+        Ok(args.data[..len].to_vec())
+    }
+
+    /// Issue a SET_REPORT request, returns the number of bytes sent
+    pub fn set_report(&self, hid: u32, report_type: ReportType, data: &[u8]) -> Result<usize> {
+        let mut args = RequestArgs::new(hid, data, data.len())?;
+        args.report_type = report_type as u32;
+        args.request_type = HID_REQ_SET_REPORT;
+        let args = self.run(self.skel.progs().hw_request(), args)?;
+        Ok(args.retval as usize)
+    }
+
+    /// Send an output report on the device's interrupt endpoint,
+    /// returns the number of bytes sent
+    pub fn output_report(&self, hid: u32, data: &[u8]) -> Result<usize> {
+        let args = RequestArgs::new(hid, data, data.len())?;
+        let args = self.run(self.skel.progs().hw_output_report(), args)?;
+        Ok(args.retval as usize)
+    }
+}
@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! Detached ed25519 signatures of bpf.o files.
+//!
+//! The signature of 0010-foo.bpf.o is the raw 64-byte signature of the
+//! object data (after decompression or extraction from a bundle), stored
+//! as 0010-foo.bpf.o.sig next to the file or its bundle. It can be created
+//! with `udev-hid-bpf install --sign-key` or with openssl:
+//!
+//!   openssl pkeyutl -sign -rawin -inkey key.pem -in 0010-foo.bpf.o -out 0010-foo.bpf.o.sig
+//!
+//! The trusted public keys are PEM files in KEYS_DIR, e.g. as created by
+//! `openssl pkey -in key.pem -pubout -out /etc/udev-hid-bpf/keys/key.pem`.
+
+use crate::manifest;
+use crate::objfile;
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The directory with the trusted public keys
+pub static KEYS_DIR: &str = "/etc/udev-hid-bpf/keys";
+/// The file with the signature policy, one of off, warn or enforce
+pub static POLICY_FILE: &str = "/etc/udev-hid-bpf/signature-policy";
+pub static SIGNATURE_SUFFIX: &str = ".sig";
+
+/// What to do with a bpf.o file that is not signed by a trusted key
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Policy {
+    /// Signatures are not checked
+    #[default]
+    Off,
+    /// Load the file but log a warning
+    Warn,
+    /// Refuse to load the file
+    Enforce,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        <Policy as clap::ValueEnum>::from_str(s.trim(), true)
+            .map_err(|_| anyhow::anyhow!("Invalid signature policy '{}'", s.trim()))
+    }
+}
+
+/// The trusted keys and hashes files are verified against,
+/// see init()
+struct Verifier {
+    policy: Policy,
+    keys: Vec<VerifyingKey>,
+    /// (path, sha256) of files recorded in a manifest by the install command
+    hashes: Vec<(PathBuf, String)>,
+}
+
+static VERIFIER: OnceLock<Verifier> = OnceLock::new();
+
+/// The policy configured in POLICY_FILE, Policy::Off if there is no such file
+pub fn configured_policy() -> Result<Policy> {
+    match std::fs::read_to_string(POLICY_FILE) {
+        Ok(policy) => policy.parse().context(format!("Invalid {POLICY_FILE}")),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Policy::Off),
+        Err(e) => Err(e).context(format!("Failed to read {POLICY_FILE}")),
+    }
+}
+
+/// Load all public keys in the directory, a missing directory has no keys
+fn load_keys(dir: &Path) -> Result<Vec<VerifyingKey>> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            std::fs::read_to_string(path)
+                .context(format!("Failed to read {path:?}"))
+                .and_then(|pem| {
+                    VerifyingKey::from_public_key_pem(&pem)
+                        .map_err(|e| anyhow::anyhow!("Invalid public key {path:?}: {e}"))
+                })
+        })
+        .collect()
+}
+
+/// Set up signature verification of all files loaded by
+/// check(). Without this call no signature is checked.
+///
+/// Files whose checksum is recorded in the given manifest are trusted too.
+pub fn init(policy: Policy, keydir: &Path, manifest_path: &Path) -> Result<()> {
+    if policy == Policy::Off {
+        return Ok(());
+    }
+
+    let keys = load_keys(keydir)?;
+    let hashes = manifest::Manifest::load(manifest_path)?
+        .objects
+        .into_iter()
+        .flat_map(|o| o.files)
+        .map(|f| (f.path, f.sha256))
+        .collect();
+    if keys.is_empty() {
+        log::warn!("No trusted keys in {keydir:?}, only installed files can be verified");
+    }
+    let _ = VERIFIER.set(Verifier {
+        policy,
+        keys,
+        hashes,
+    });
+    Ok(())
+}
+
+/// The path of the signature of a bpf.o file, next to the file or its bundle
+pub fn signature_path(path: &Path) -> PathBuf {
+    let dir = match path.parent() {
+        Some(p) if p.is_file() => p.parent(),
+        p => p,
+    };
+    dir.unwrap_or(Path::new(""))
+        .join(objfile::filename(path) + SIGNATURE_SUFFIX)
+}
+
+/// Verify the data of the bpf.o file at path against its detached
+/// signature, the signature must be made by any of the keys
+fn verify_signature(keys: &[VerifyingKey], path: &Path, data: &[u8]) -> Result<()> {
+    let sigpath = signature_path(path);
+    let sig = std::fs::read(&sigpath).context(format!("No signature {sigpath:?}"))?;
+    let sig = Signature::from_slice(&sig).context(format!("Invalid signature {sigpath:?}"))?;
+    if !keys.iter().any(|key| key.verify_strict(data, &sig).is_ok()) {
+        bail!("{sigpath:?} is not signed by a trusted key");
+    }
+    Ok(())
+}
+
+impl Verifier {
+    fn verify(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let sha256 = manifest::sha256(data);
+        if self
+            .hashes
+            .iter()
+            .any(|(p, hash)| p == path && *hash == sha256)
+        {
+            return Ok(());
+        }
+        verify_signature(&self.keys, path, data)
+    }
+}
+
+/// Check the data of the bpf.o file at path against the configured policy,
+/// see init(). Returns an error if the file must not be loaded.
+pub fn check(path: &Path, data: &[u8]) -> Result<()> {
+    let Some(verifier) = VERIFIER.get() else {
+        return Ok(());
+    };
+    match (verifier.verify(path, data), verifier.policy) {
+        (Ok(_), _) => {
+            log::debug!("Verified signature of {path:?}");
+            Ok(())
+        }
+        (Err(e), Policy::Enforce) => Err(e.context("signature verification failed")),
+        (Err(e), _) => {
+            log::warn!("Loading unverified {path:?}: {e:#}");
+            Ok(())
+        }
+    }
+}
+
+/// Load the private key from the given PKCS#8 PEM file,
+/// e.g. as created by `openssl genpkey -algorithm ed25519`
+pub fn load_signing_key(path: &Path) -> Result<SigningKey> {
+    let pem = std::fs::read_to_string(path).context(format!("Failed to read {path:?}"))?;
+    SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|e| anyhow::anyhow!("Invalid private key {path:?}: {e}"))
+}
+
+/// The detached signature of the data
+pub fn sign(key: &SigningKey, data: &[u8]) -> Vec<u8> {
+    key.sign(data).to_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        assert_eq!("off".parse::<Policy>().unwrap(), Policy::Off);
+        assert_eq!("Enforce\n".parse::<Policy>().unwrap(), Policy::Enforce);
+        assert!("strict".parse::<Policy>().is_err());
+    }
+
+    #[test]
+    fn test_signature_path() {
+        assert_eq!(
+            signature_path(Path::new("/lib/firmware/hid/bpf/0010-foo.bpf.o.zst")),
+            PathBuf::from("/lib/firmware/hid/bpf/0010-foo.bpf.o.sig")
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("0010-foo.bpf.o");
+        let data = b"not really an object";
+
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let verifier = Verifier {
+            policy: Policy::Enforce,
+            keys: vec![key.verifying_key()],
+            hashes: vec![(tmpdir.path().join("0020-bar.bpf.o"), manifest::sha256(data))],
+        };
+
+        // no signature
+        assert!(verifier.verify(&path, data).is_err());
+
+        std::fs::write(signature_path(&path), sign(&key, data)).unwrap();
+        assert!(verifier.verify(&path, data).is_ok());
+        assert!(verifier.verify(&path, b"modified").is_err());
+
+        std::fs::write(signature_path(&path), sign(&other, data)).unwrap();
+        assert!(verifier.verify(&path, data).is_err());
+
+        // recorded by install, no signature needed
+        assert!(verifier
+            .verify(&tmpdir.path().join("0020-bar.bpf.o"), data)
+            .is_ok());
+    }
+}
@@ -0,0 +1,596 @@
+// SPDX-License-Identifier: GPL-2.0-only
+
+use crate::bpf;
+use anyhow::{bail, Context, Result};
+use std::ffi::CString;
+use std::ops::Range;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+static TRACEFS_DIRS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+static INSTANCE_PREFIX: &str = "udev-hid-bpf-";
+
+/// A tracefs instance of our own, removed when dropped.
+///
+/// trace_pipe does not say which program printed a line. Our instance
+/// records the kernel stack trace of each bpf_trace_printk() event with
+/// the address of each frame so a line can be matched to the JIT-ed code
+/// of a program, without changing the options of the global trace buffer.
+pub struct TraceInstance {
+    path: PathBuf,
+}
+
+impl TraceInstance {
+    pub fn new() -> Result<Self> {
+        let instances = TRACEFS_DIRS
+            .iter()
+            .map(|dir| PathBuf::from(dir).join("instances"))
+            .find(|p| p.exists())
+            .context("Unable to find tracefs instances, is tracefs mounted?")?;
+
+        // Remove the instances of earlier runs that were killed
+        std::fs::read_dir(&instances)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(INSTANCE_PREFIX))
+                    .and_then(|pid| pid.parse::<u32>().ok())
+                    .is_some_and(|pid| !Path::new(&format!("/proc/{pid}")).exists())
+            })
+            .for_each(|entry| {
+                let _ = std::fs::remove_dir(entry.path());
+            });
+
+        let path = instances.join(format!("{INSTANCE_PREFIX}{}", std::process::id()));
+        std::fs::create_dir(&path).context(format!("Failed to create {path:?}"))?;
+        let instance = TraceInstance { path };
+
+        for (file, value) in [
+            ("options/stacktrace", "1"),
+            ("options/sym-addr", "1"),
+            ("events/bpf_trace/bpf_trace_printk/enable", "1"),
+        ] {
+            let path = instance.path.join(file);
+            std::fs::write(&path, value).context(format!("Failed to write {path:?}"))?;
+        }
+
+        Ok(instance)
+    }
+
+    pub fn trace_pipe(&self) -> PathBuf {
+        self.path.join("trace_pipe")
+    }
+}
+
+impl Drop for TraceInstance {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir(&self.path);
+    }
+}
+
+/// A bpf_printk() line read from trace_pipe
+#[derive(Debug, PartialEq)]
+pub struct TraceLine<'a> {
+    pub timestamp: &'a str,
+    pub message: &'a str,
+}
+
+impl<'a> TraceLine<'a> {
+    /// Parse a trace_pipe line like
+    /// `<idle>-0  [003] d.h2. 1234.567890: bpf_trace_printk: message`,
+    /// lines of other trace events return None.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let (head, message) = line.split_once(": bpf_trace_printk: ")?;
+        let timestamp = head.rsplit(' ').next()?;
+        Some(TraceLine {
+            timestamp,
+            message: message.trim_end(),
+        })
+    }
+}
+
+/// The address of a stack trace frame recorded with the sym-addr option,
+/// e.g. ` => bpf_prog_6deef7357e7b4530_hid_fix_event <ffffffffc0a1b2c3>`
+pub fn stack_frame(line: &str) -> Option<u64> {
+    let frame = line.trim().strip_prefix("=> ")?;
+    let (_, address) = frame.rsplit_once(" <")?;
+    u64::from_str_radix(address.strip_suffix('>')?, 16).ok()
+}
+
+/// What EventDecoder::feed() did with a message
+#[derive(Debug, PartialEq)]
+pub enum Decoded {
+    /// The message is part of a hid_bpf_printk_event() dump
+    Partial,
+    /// The message completed a hid_bpf_printk_event() dump of this report
+    Report(Vec<u8>),
+    /// The message is not part of a hid_bpf_printk_event() dump
+    Other,
+}
+
+/// Reassembles the reports dumped by hid_bpf_printk_event() as
+/// `event: size: 8` followed by one ` 0x00000000: 01 02 ...` line
+/// per 64 bytes.
+#[derive(Default)]
+pub struct EventDecoder {
+    size: Option<usize>,
+    data: Vec<u8>,
+}
+
+impl EventDecoder {
+    pub fn feed(&mut self, message: &str) -> Decoded {
+        let message = message.trim();
+        if let Some(size) = message.strip_prefix("event: size: ") {
+            if let Ok(size) = size.parse::<usize>() {
+                self.size = Some(size);
+                self.data.clear();
+                return Decoded::Partial;
+            }
+        }
+
+        let Some(size) = self.size else {
+            return Decoded::Other;
+        };
+        let Some((offset, bytes)) = message
+            .strip_prefix("0x")
+            .and_then(|m| m.split_once(": "))
+            .and_then(|(offset, bytes)| Some((usize::from_str_radix(offset, 16).ok()?, bytes)))
+        else {
+            return Decoded::Other;
+        };
+
+        let bytes: Option<Vec<u8>> = bytes
+            .split_whitespace()
+            .map(|b| u8::from_str_radix(b, 16).ok())
+            .collect();
+        let Some(bytes) = bytes else {
+            return Decoded::Other;
+        };
+
+        self.data.truncate(offset);
+        self.data.extend(bytes);
+
+        // The helper's string buffer is not cleared between packets,
+        // anything past the event size in the last packet is stale
+        if self.data.len() >= size {
+            self.size = None;
+            self.data.truncate(size);
+            Decoded::Report(std::mem::take(&mut self.data))
+        } else {
+            Decoded::Partial
+        }
+    }
+}
+
+/// A program attached to a device
+pub struct Program {
+    pub id: u32,
+    pub name: String,
+    /// The address range of each of the program's JIT-ed functions
+    ranges: Vec<Range<u64>>,
+}
+
+impl Program {
+    fn from_id(id: u32) -> Option<Self> {
+        let fd = owned_fd(unsafe { libbpf_sys::bpf_prog_get_fd_by_id(id) })?;
+        let info = prog_info(&fd)?;
+
+        let mut ksyms = vec![0u64; info.nr_jited_ksyms as usize];
+        let mut lens = vec![0u32; info.nr_jited_func_lens as usize];
+        let mut jited = libbpf_sys::bpf_prog_info {
+            nr_jited_ksyms: ksyms.len() as u32,
+            jited_ksyms: ksyms.as_mut_ptr() as u64,
+            nr_jited_func_lens: lens.len() as u32,
+            jited_func_lens: lens.as_mut_ptr() as u64,
+            ..Default::default()
+        };
+        let mut len = std::mem::size_of::<libbpf_sys::bpf_prog_info>() as u32;
+        if unsafe { libbpf_sys::bpf_prog_get_info_by_fd(fd.as_raw_fd(), &mut jited, &mut len) } != 0
+        {
+            return None;
+        }
+
+        Some(Program {
+            id,
+            name: prog_name(&info),
+            ranges: ksyms
+                .iter()
+                .zip(lens.iter())
+                .map(|(start, len)| *start..start + u64::from(*len))
+                .collect(),
+        })
+    }
+}
+
+/// The BPF programs attached to a device, found through
+/// the links pinned in the device's bpffs directory.
+pub struct DevicePrograms {
+    pub programs: Vec<Program>,
+}
+
+fn owned_fd(fd: std::os::raw::c_int) -> Option<OwnedFd> {
+    match fd {
+        fd if fd >= 0 => Some(unsafe { OwnedFd::from_raw_fd(fd) }),
+        _ => None,
+    }
+}
+
+/// The kind of bpf object behind a fd, from its /proc/self/fd link
+/// which is e.g. "anon_inode:bpf-prog"
+fn bpf_fd_kind(fd: &OwnedFd) -> Option<String> {
+    let target = std::fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd())).ok()?;
+    target
+        .to_str()
+        .and_then(|t| t.strip_prefix("anon_inode:"))
+        .map(String::from)
+}
+
+fn prog_info(fd: &OwnedFd) -> Option<libbpf_sys::bpf_prog_info> {
+    let mut info = libbpf_sys::bpf_prog_info::default();
+    let mut len = std::mem::size_of::<libbpf_sys::bpf_prog_info>() as u32;
+    match unsafe { libbpf_sys::bpf_prog_get_info_by_fd(fd.as_raw_fd(), &mut info, &mut len) } {
+        0 => Some(info),
+        _ => None,
+    }
+}
+
+fn link_info(fd: &OwnedFd) -> Option<libbpf_sys::bpf_link_info> {
+    let mut info: libbpf_sys::bpf_link_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libbpf_sys::bpf_link_info>() as u32;
+    match unsafe { libbpf_sys::bpf_link_get_info_by_fd(fd.as_raw_fd(), &mut info, &mut len) } {
+        0 => Some(info),
+        _ => None,
+    }
+}
+
+fn map_info(fd: &OwnedFd) -> Option<libbpf_sys::bpf_map_info> {
+    let mut info = libbpf_sys::bpf_map_info::default();
+    let mut len = std::mem::size_of::<libbpf_sys::bpf_map_info>() as u32;
+    match unsafe { libbpf_sys::bpf_map_get_info_by_fd(fd.as_raw_fd(), &mut info, &mut len) } {
+        0 => Some(info),
+        _ => None,
+    }
+}
+
+fn pinned_object(path: &Path) -> Option<OwnedFd> {
+    let cpath = CString::new(path.to_str()?).ok()?;
+    owned_fd(unsafe { libbpf_sys::bpf_obj_get(cpath.as_ptr()) })
+}
+
+/// The id of a pinned link, None for anything else
+fn pinned_link_id(path: &Path) -> Option<u32> {
+    let fd = pinned_object(path)?;
+    match bpf_fd_kind(&fd)?.as_str() {
+        "bpf-link" => link_info(&fd).map(|info| info.id),
+        _ => None,
+    }
+}
+
+/// The kernel's BTF, split BTF if HID is built as module
+struct Btf {
+    vmlinux: *mut libbpf_sys::btf,
+    hid: *mut libbpf_sys::btf,
+}
+
+impl Drop for Btf {
+    fn drop(&mut self) {
+        unsafe {
+            libbpf_sys::btf__free(self.hid);
+            libbpf_sys::btf__free(self.vmlinux);
+        }
+    }
+}
+
+impl Btf {
+    fn load() -> Option<Self> {
+        let vmlinux = unsafe { libbpf_sys::btf__load_vmlinux_btf() };
+        if vmlinux.is_null() {
+            return None;
+        }
+        let module = b"hid\0";
+        let hid = unsafe { libbpf_sys::btf__load_module_btf(module.as_ptr() as *const _, vmlinux) };
+        Some(Btf { vmlinux, hid })
+    }
+
+    fn btf(&self) -> *const libbpf_sys::btf {
+        if self.hid.is_null() {
+            self.vmlinux
+        } else {
+            self.hid
+        }
+    }
+
+    fn name(&self, offset: u32) -> Option<&str> {
+        let name = unsafe { libbpf_sys::btf__name_by_offset(self.btf(), offset) };
+        if name.is_null() {
+            return None;
+        }
+        unsafe { std::ffi::CStr::from_ptr(name) }.to_str().ok()
+    }
+
+    /// The type with the given id, skipping typedefs and qualifiers
+    fn resolve(&self, mut id: u32) -> Option<&libbpf_sys::btf_type> {
+        loop {
+            let t = unsafe { libbpf_sys::btf__type_by_id(self.btf(), id).as_ref() }?;
+            match btf_kind(t) {
+                libbpf_sys::BTF_KIND_TYPEDEF
+                | libbpf_sys::BTF_KIND_VOLATILE
+                | libbpf_sys::BTF_KIND_CONST
+                | libbpf_sys::BTF_KIND_RESTRICT
+                | libbpf_sys::BTF_KIND_TYPE_TAG => id = unsafe { t.__bindgen_anon_1.type_ },
+                _ => return Some(t),
+            }
+        }
+    }
+
+    /// The byte offsets of the function pointers in a struct_ops map value,
+    /// i.e. in the `data` member of e.g. `struct bpf_struct_ops_hid_bpf_ops`
+    fn func_ptr_offsets(&self, value_type_id: u32) -> Option<Vec<usize>> {
+        let value = self.resolve(value_type_id)?;
+        let data = btf_members(value)
+            .iter()
+            .find(|m| self.name(m.name_off) == Some("data"))?;
+        let ops = self.resolve(data.type_)?;
+
+        let offsets = btf_members(ops)
+            .iter()
+            .filter(|m| {
+                self.resolve(m.type_)
+                    .filter(|t| btf_kind(t) == libbpf_sys::BTF_KIND_PTR)
+                    .and_then(|t| self.resolve(unsafe { t.__bindgen_anon_1.type_ }))
+                    .is_some_and(|t| btf_kind(t) == libbpf_sys::BTF_KIND_FUNC_PROTO)
+            })
+            .map(|m| btf_member_offset(value, data) + btf_member_offset(ops, m))
+            .collect();
+        Some(offsets)
+    }
+}
+
+fn btf_kind(t: &libbpf_sys::btf_type) -> u32 {
+    (t.info >> 24) & 0x1f
+}
+
+fn btf_members(t: &libbpf_sys::btf_type) -> &[libbpf_sys::btf_member] {
+    match btf_kind(t) {
+        libbpf_sys::BTF_KIND_STRUCT | libbpf_sys::BTF_KIND_UNION => {
+            // The members follow the type
+            let members = unsafe { (t as *const libbpf_sys::btf_type).add(1) };
+            let vlen = (t.info & 0xffff) as usize;
+            unsafe { std::slice::from_raw_parts(members as *const libbpf_sys::btf_member, vlen) }
+        }
+        _ => &[],
+    }
+}
+
+/// The offset of a member in bytes
+fn btf_member_offset(t: &libbpf_sys::btf_type, member: &libbpf_sys::btf_member) -> usize {
+    // With kind_flag set the high 8 bits are the bitfield size
+    let bits = if t.info >> 31 != 0 {
+        member.offset & 0xffffff
+    } else {
+        member.offset
+    };
+    bits as usize / 8
+}
+
+/// The ids of the programs in a struct_ops map. The kernel stores
+/// each program's id in place of its function pointer in the map's
+/// value, the layout of which is described by the kernel's BTF.
+fn struct_ops_prog_ids(map_id: u32) -> Option<Vec<u32>> {
+    let fd = owned_fd(unsafe { libbpf_sys::bpf_map_get_fd_by_id(map_id) })?;
+    let info = map_info(&fd)?;
+
+    let key = 0u32;
+    let mut value = vec![0u8; info.value_size as usize];
+    let rc = unsafe {
+        libbpf_sys::bpf_map_lookup_elem(
+            fd.as_raw_fd(),
+            &key as *const u32 as *const libc::c_void,
+            value.as_mut_ptr() as *mut libc::c_void,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+
+    let btf = Btf::load()?;
+    let ids = btf
+        .func_ptr_offsets(info.btf_vmlinux_value_type_id)?
+        .iter()
+        .filter_map(|offset| value.get(*offset..offset + 8))
+        .map(|ptr| u64::from_ne_bytes(ptr.try_into().unwrap()) as u32)
+        .filter(|id| *id != 0)
+        .collect();
+    Some(ids)
+}
+
+/// The ids of the programs behind a pinned program or link
+fn pinned_program_ids(path: &Path) -> Vec<u32> {
+    let Some(fd) = pinned_object(path) else {
+        return Vec::new();
+    };
+
+    match bpf_fd_kind(&fd).as_deref() {
+        Some("bpf-prog") => prog_info(&fd).map(|info| vec![info.id]).unwrap_or_default(),
+        Some("bpf-link") => match link_info(&fd) {
+            Some(info) if info.type_ == libbpf_sys::BPF_LINK_TYPE_STRUCT_OPS => {
+                let map_id = unsafe { info.__bindgen_anon_1.struct_ops.map_id };
+                struct_ops_prog_ids(map_id).unwrap_or_default()
+            }
+            Some(info) if info.prog_id != 0 => vec![info.prog_id],
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn find_pins(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .flat_map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                find_pins(&path)
+            } else {
+                vec![path]
+            }
+        })
+        .collect()
+}
+
+fn prog_name(info: &libbpf_sys::bpf_prog_info) -> String {
+    let name: Vec<u8> = info
+        .name
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&name).into_owned()
+}
+
+/// The ids of the programs pinned in a directory, in order
+fn program_ids(dir: &Path) -> Vec<u32> {
+    let mut ids: Vec<u32> = find_pins(dir)
+        .iter()
+        .flat_map(|p| pinned_program_ids(p))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+impl DevicePrograms {
+    pub fn from_sysname(sysname: &str) -> Result<Self> {
+        let dir = PathBuf::from(bpf::get_bpffs_path(sysname, ""));
+        let programs: Vec<Program> = program_ids(&dir)
+            .into_iter()
+            .filter_map(Program::from_id)
+            .collect();
+
+        if programs.is_empty() {
+            bail!("No BPF programs are loaded for {sysname}");
+        }
+
+        Ok(DevicePrograms { programs })
+    }
+
+    /// Whether the code at the address belongs to one of the programs
+    pub fn contains(&self, address: u64) -> bool {
+        self.programs
+            .iter()
+            .flat_map(|p| p.ranges.iter())
+            .any(|range| range.contains(&address))
+    }
+}
+
+/// A bpf object attached to a device
+pub struct AttachedObject {
+    /// The object's directory in the device's bpffs directory
+    pub name: String,
+    /// The names of the object's programs
+    pub programs: Vec<String>,
+}
+
+/// The objects attached to a device in the order they were attached in,
+/// which is the order the kernel runs their programs in. The kernel does
+/// not expose that order, it is inferred from the link ids: both backends
+/// pin a link per attachment and link ids are allocated in attach order.
+pub fn attach_chain(sysname: &str) -> Result<Vec<AttachedObject>> {
+    let dir = PathBuf::from(bpf::get_bpffs_path(sysname, ""));
+    let mut objects: Vec<(Option<u32>, AttachedObject)> = std::fs::read_dir(&dir)
+        .with_context(|| format!("No BPF programs are loaded for {sysname}"))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .map(|path| {
+            let first_link = find_pins(&path)
+                .iter()
+                .filter_map(|p| pinned_link_id(p))
+                .min();
+            let object = AttachedObject {
+                name: String::from(path.file_name().unwrap_or_default().to_string_lossy()),
+                programs: program_ids(&path)
+                    .into_iter()
+                    .filter_map(Program::from_id)
+                    .map(|p| p.name)
+                    .collect(),
+            };
+            (first_link, object)
+        })
+        .collect();
+
+    // Objects without a link are listed last
+    objects.sort_by_key(|(link, _)| link.unwrap_or(u32::MAX));
+
+    Ok(objects.into_iter().map(|(_, object)| object).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_line() {
+        let line =
+            "          <idle>-0       [003] d.h2. 12345.678901: bpf_trace_printk: event: size: 8";
+        assert_eq!(
+            TraceLine::parse(line),
+            Some(TraceLine {
+                timestamp: "12345.678901",
+                message: "event: size: 8",
+            })
+        );
+
+        let line = "   kworker/0:1-42  [000] ..... 1.000000: sched_switch: prev_comm=foo";
+        assert_eq!(TraceLine::parse(line), None);
+    }
+
+    #[test]
+    fn test_stack_frame() {
+        assert_eq!(
+            stack_frame(" => bpf_prog_6deef7357e7b4530_hid_fix_event <ffffffffc0a1b2c3>\n"),
+            Some(0xffffffffc0a1b2c3)
+        );
+        assert_eq!(stack_frame(" => hid_input_report"), None);
+        let line = "          <idle>-0       [003] d.h2. 12345.678901: <stack trace>";
+        assert_eq!(stack_frame(line), None);
+    }
+
+    #[test]
+    fn test_event_decoder() {
+        let mut decoder = EventDecoder::default();
+        assert_eq!(decoder.feed("surface dial called"), Decoded::Other);
+        assert_eq!(decoder.feed("event: size: 3"), Decoded::Partial);
+        // stale bytes from a previous event after the first three
+        assert_eq!(
+            decoder.feed(" 0x00000000: 01 fe 03 aa bb "),
+            Decoded::Report(vec![0x01, 0xfe, 0x03])
+        );
+        assert_eq!(decoder.feed(" 0x00000000: 01 02 03 "), Decoded::Other);
+
+        let mut decoder = EventDecoder::default();
+        assert_eq!(decoder.feed("event: size: 66"), Decoded::Partial);
+        let first: String = (0..64).map(|b| format!("{b:02x} ")).collect();
+        assert_eq!(
+            decoder.feed(&format!(" 0x00000000: {first}")),
+            Decoded::Partial
+        );
+        assert_eq!(
+            decoder.feed(" 0x00000040: 40 41 42 43 "),
+            Decoded::Report((0..66).collect())
+        );
+
+        // no short packet follows a report of exactly one packet
+        assert_eq!(decoder.feed("event: size: 64"), Decoded::Partial);
+        assert_eq!(
+            decoder.feed(&format!(" 0x00000000: {first}")),
+            Decoded::Report((0..64).collect())
+        );
+    }
+}